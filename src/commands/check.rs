@@ -0,0 +1,67 @@
+use crate::error::{Error, Result};
+use crate::ansi::*;
+use crate::napm::Napm;
+use crate::output::{OutputFormat, print_json, print_tsv};
+
+pub fn run(napm: &Napm, packages: &[&str], format: OutputFormat) -> Result<()> {
+    let results = napm.check_pkgs(packages)?;
+
+    let problems = results
+        .iter()
+        .flat_map(|r| &r.problems)
+        .filter(|p| p.kind.is_problem())
+        .count();
+
+    match format {
+        OutputFormat::Text => {
+            for result in &results {
+                for problem in &result.problems {
+                    let color = if problem.kind.is_problem() { ANSI_RED } else { ANSI_YELLOW };
+
+                    let details = match (&problem.expected, &problem.found) {
+                        (Some(expected), Some(found)) => format!(" (expected {expected}, found {found})"),
+                        _ => String::new(),
+                    };
+
+                    println!(
+                        "{}: {color}{}{ANSI_RESET} {ANSI_BLUE}{}{ANSI_RESET}{details}",
+                        result.package.formatted_name(false),
+                        problem.kind.describe(),
+                        problem.path,
+                    );
+                }
+            }
+
+            let checked: usize = results.iter().map(|r| r.checked).sum();
+            let backups = results
+                .iter()
+                .flat_map(|r| &r.problems)
+                .filter(|p| !p.kind.is_problem())
+                .count();
+
+            println!(
+                "{} package(s), {checked} file(s) checked: {problems} problem(s), {backups} modified backup file(s)",
+                results.len(),
+            );
+        }
+        OutputFormat::Json => print_json(&results)?,
+        OutputFormat::Tsv => print_tsv(
+            results
+                .iter()
+                .flat_map(|r| r.problems.iter().map(move |p| [
+                    r.package.name.clone(),
+                    r.package.version.clone(),
+                    p.kind.key().to_string(),
+                    p.path.clone(),
+                    p.expected.clone().unwrap_or_default(),
+                    p.found.clone().unwrap_or_default(),
+                ]))
+        )?,
+    }
+
+    if problems > 0 {
+        return Err(Error::CheckFailed(problems));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,11 @@
+use std::path::PathBuf;
+
+use crate::error::Result;
+use crate::napm::Napm;
+use crate::util::require_root;
+
+pub fn run(napm: &mut Napm, pkg_name: &str, archive_dir: Option<PathBuf>) -> Result<()> {
+    require_root()?;
+
+    napm.downgrade(pkg_name, archive_dir.as_deref())
+}
@@ -1,10 +1,19 @@
 use crate::error::Result;
 use crate::napm::Napm;
+use crate::output::{OutputFormat, print_json, print_tsv};
 
-pub fn run(napm: &mut Napm, pkg_name: &str, with_dirs: bool) -> Result<()> {
-    for f in napm.files(pkg_name, with_dirs)? {
-        println!("{}", f);
-    }
+pub fn run(napm: &mut Napm, pkg_name: &str, with_dirs: bool, format: OutputFormat) -> Result<()> {
+    let files = napm.files(pkg_name, with_dirs)?;
+
+    match format {
+        OutputFormat::Text => {
+            for f in files {
+                println!("{}", f);
+            }
 
-    Ok(())
+            Ok(())
+        }
+        OutputFormat::Json => print_json(&files),
+        OutputFormat::Tsv => print_tsv(files.iter().map(|f| [f])),
+    }
 }
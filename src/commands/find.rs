@@ -1,20 +1,46 @@
+use serde::Serialize;
+
 use crate::error::{Error, Result};
 use crate::ansi::*;
 use crate::napm::Napm;
+use crate::napm::cache::FindOptions;
+use crate::output::{OutputFormat, print_json, print_tsv};
+use crate::pkg::Pkg;
+
+#[derive(Serialize)]
+struct FindResult<'a> {
+    package: &'a Pkg,
+    path: &'a str,
+}
 
-pub fn run(napm: &mut Napm, path: String, exact: bool) -> Result<()> {
-    let results = napm.find(path, exact)?;
+pub fn run(napm: &mut Napm, path: String, opts: FindOptions, format: OutputFormat) -> Result<()> {
+    let results = napm.find(path, &opts)?;
     
-    if results.is_empty() {
+    if results.is_empty() && format.is_text() {
         return Err(Error::NoResults);
     }
 
-    for (pkg, path) in results {
-        println!(
-            "{}: {ANSI_BLUE}{}{ANSI_RESET}",
-            pkg.formatted_name(false), path
-        );
-    }
+    match format {
+        OutputFormat::Text => {
+            for (pkg, path) in results {
+                println!(
+                    "{}: {ANSI_BLUE}{}{ANSI_RESET}",
+                    pkg.formatted_name(false), path
+                );
+            }
 
-    Ok(())
+            Ok(())
+        }
+        OutputFormat::Json => print_json(
+            &results
+                .iter()
+                .map(|(package, path)| FindResult { package, path })
+                .collect::<Vec<_>>()
+        ),
+        OutputFormat::Tsv => print_tsv(
+            results
+                .iter()
+                .map(|(p, path)| [&p.name, &p.version, &p.repo, path])
+        ),
+    }
 }
@@ -1,14 +1,162 @@
+use serde::Serialize;
+
 use crate::error::Result;
+use crate::ansi::*;
 use crate::napm::Napm;
+use crate::output::{OutputFormat, print_json, print_tsv};
+use crate::pkg::PkgInfo;
+use crate::util::{format_size, format_timestamp};
+
+const LABEL_WIDTH: usize = 16;
+const COLUMN_WIDTH: usize = 40;
+
+#[derive(Serialize)]
+struct InfoResult<'a> {
+    sync: Option<&'a PkgInfo>,
+    local: Option<&'a PkgInfo>,
+}
+
+/// Every displayed field as a list of items, lists are empty when unknown
+fn fields(p: &PkgInfo) -> Vec<(&'static str, Vec<String>)> {
+    let one = |s: String| vec![s];
+    let opt = |s: &Option<String>| s.iter().cloned().collect::<Vec<_>>();
+
+    vec![
+        ("Name", one(p.name.clone())),
+        ("Version", one(p.version.clone())),
+        ("Description", one(p.desc.clone())),
+        ("Architecture", opt(&p.arch)),
+        ("Repository", one(p.repo.clone())),
+        ("URL", opt(&p.url)),
+        ("Licenses", p.licenses.clone()),
+        ("Groups", p.groups.clone()),
+        ("Provides", p.provides.clone()),
+        ("Depends On", p.depends.clone()),
+        ("Optional Deps", p.optdepends
+            .iter()
+            .map(|o| format!(
+                "{}{}{}",
+                o.dep,
+                o.desc.as_ref().map(|d| format!(": {d}")).unwrap_or_default(),
+                if o.installed { " [installed]" } else { "" }
+            ))
+            .collect()),
+        ("Required By", p.required_by.clone()),
+        ("Optional For", p.optional_for.clone()),
+        ("Conflicts With", p.conflicts.clone()),
+        ("Replaces", p.replaces.clone()),
+        ("Download Size", p.download_size.map(format_size).into_iter().collect()),
+        ("Installed Size", one(format_size(p.installed_size))),
+        ("Packager", opt(&p.packager)),
+        ("Build Date", one(format_timestamp(p.build_date))),
+        ("Install Date", p.install_date.map(format_timestamp).into_iter().collect()),
+        ("Install Reason", opt(&p.install_reason)),
+        ("Validated By", p.validation.clone()),
+    ]
+}
+
+/// Lays out items into lines of at most `width` characters, one item per line for optional deps
+fn wrap(items: &[String], width: usize, one_per_line: bool) -> Vec<String> {
+    if items.is_empty() {
+        return vec!["None".to_string()];
+    }
+
+    if one_per_line {
+        return items.to_vec();
+    }
+
+    let mut lines = vec![String::new()];
+
+    for item in items {
+        let line = lines.last_mut().unwrap();
+
+        if !line.is_empty() && line.len() + 2 + item.len() > width {
+            lines.push(item.clone());
+        } else {
+            if !line.is_empty() {
+                line.push_str("  ");
+            }
+            line.push_str(item);
+        }
+    }
+
+    lines
+}
+
+fn print_text(sync: Option<&PkgInfo>, local: Option<&PkgInfo>) {
+    match (sync, local) {
+        (Some(sync), Some(local)) => {
+            println!(
+                "{:<LABEL_WIDTH$}  {ANSI_BOLD}{:<COLUMN_WIDTH$}{ANSI_RESET}  {ANSI_BOLD}Installed{ANSI_RESET}",
+                "",
+                format!("Sync ({})", sync.repo),
+            );
+
+            for ((label, sync_items), (_, local_items)) in fields(sync).into_iter().zip(fields(local)) {
+                let one_per_line = label == "Optional Deps";
+                let sync_lines = wrap(&sync_items, COLUMN_WIDTH, one_per_line);
+                let local_lines = wrap(&local_items, COLUMN_WIDTH, one_per_line);
+                let differs = sync_items != local_items;
+
+                for i in 0..sync_lines.len().max(local_lines.len()) {
+                    let label = if i == 0 { label } else { "" };
+                    let sync_line = sync_lines.get(i).map(String::as_str).unwrap_or("");
+                    let local_line = local_lines.get(i).map(String::as_str).unwrap_or("");
+                    let sep = if i == 0 { ":" } else { " " };
+
+                    if differs {
+                        println!("{label:<LABEL_WIDTH$}{sep} {sync_line:<COLUMN_WIDTH$}  {ANSI_YELLOW}{local_line}{ANSI_RESET}");
+                    } else {
+                        println!("{label:<LABEL_WIDTH$}{sep} {sync_line:<COLUMN_WIDTH$}  {local_line}");
+                    }
+                }
+            }
+        }
+        (Some(p), None) | (None, Some(p)) => {
+            for (label, items) in fields(p) {
+                let lines = wrap(&items, usize::MAX, label == "Optional Deps");
+
+                for (i, line) in lines.iter().enumerate() {
+                    if i == 0 {
+                        println!("{label:<LABEL_WIDTH$}: {line}");
+                    } else {
+                        println!("{:<LABEL_WIDTH$}  {line}", "");
+                    }
+                }
+            }
+        }
+        (None, None) => (),
+    }
+}
+
+pub fn run(napm: &Napm, pkg: &str, format: OutputFormat) -> Result<()> {
+    let (sync, local) = napm.pkg_info(pkg)?;
+
+    match format {
+        OutputFormat::Text => {
+            print_text(sync.as_ref(), local.as_ref());
 
-pub fn run(napm: &Napm, pkg: &str) -> Result<()> {
-    let p = napm.info(pkg)?;
+            // TODO: link to `packages.neoarchlinux.org/package/{pkg}` once the website is created
 
-    println!("Name          : {}", p.name);
-    println!("Version       : {}", p.version);
-    println!("Description   : {}", p.desc);
+            Ok(())
+        }
+        OutputFormat::Json => print_json(&InfoResult {
+            sync: sync.as_ref(),
+            local: local.as_ref(),
+        }),
+        OutputFormat::Tsv => {
+            let sync_fields = sync.as_ref().map(fields);
+            let local_fields = local.as_ref().map(fields);
+            let labels = sync_fields.as_ref().or(local_fields.as_ref()).unwrap();
 
-    // TODO: more info + link to `packages.neoarchlinux.org/package/{pkg}` once the website is created
+            print_tsv(labels.iter().enumerate().map(|(i, (label, _))| {
+                let value = |f: &Option<Vec<(&str, Vec<String>)>>| f
+                    .as_ref()
+                    .map(|f| f[i].1.join(" "))
+                    .unwrap_or_default();
 
-    Ok(())
+                [label.to_string(), value(&sync_fields), value(&local_fields)]
+            }))
+        }
+    }
 }
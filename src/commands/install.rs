@@ -1,14 +1,23 @@
+use alpm::PackageReason;
+
 use crate::error::{Error, Result};
 use crate::log_error;
 use crate::util::{confirm, require_root};
 use crate::napm::Napm;
+use crate::pkg::PkgFile;
 
-pub fn run(napm: &mut Napm, pkg_names: &[&str]) -> Result<()> {
+pub fn run(napm: &mut Napm, targets: &[&str], reason: Option<PackageReason>) -> Result<()> {
     require_root()?;
-    
+
+    let (file_targets, pkg_names): (Vec<&str>, Vec<&str>) = targets
+        .iter()
+        .partition(|target| PkgFile::is_target(target));
+
+    let files = napm.pkg_files(&file_targets)?;
+
     let pkgs = {
         let pkgs_res = napm
-            .pkgs(pkg_names)
+            .pkgs(&pkg_names)
             .into_iter()
             .map(|pkg| {
                 if let Ok(ref p) = pkg && let Ok(_) = napm.local_pkg(&p.name) {
@@ -42,7 +51,7 @@ pub fn run(napm: &mut Napm, pkg_names: &[&str]) -> Result<()> {
             }
         }
 
-        if display_names.is_empty() {
+        if display_names.is_empty() && files.is_empty() {
             return Err(Error::NoValidPackage);
         }
 
@@ -52,5 +61,5 @@ pub fn run(napm: &mut Napm, pkg_names: &[&str]) -> Result<()> {
             .collect::<Vec<_>>()
     };
 
-    napm.install_pkgs(&pkgs)
+    napm.install_pkgs(&pkgs, &files, reason)
 }
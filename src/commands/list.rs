@@ -1,10 +1,19 @@
 use crate::error::Result;
 use crate::napm::Napm;
+use crate::output::{OutputFormat, print_json, print_tsv};
 
-pub fn run(napm: &Napm) -> Result<()> {
-    for pkg in napm.list() {
-        println!("{}", pkg.formatted_name(true));
-    }
+pub fn run(napm: &Napm, format: OutputFormat) -> Result<()> {
+    let pkgs = napm.list();
+
+    match format {
+        OutputFormat::Text => {
+            for pkg in pkgs {
+                println!("{}", pkg.formatted_name(true));
+            }
 
-    Ok(())
+            Ok(())
+        }
+        OutputFormat::Json => print_json(&pkgs),
+        OutputFormat::Tsv => print_tsv(pkgs.iter().map(|p| [&p.name, &p.version, &p.repo, &p.desc])),
+    }
 }
@@ -0,0 +1,11 @@
+use alpm::PackageReason;
+
+use crate::error::Result;
+use crate::napm::Napm;
+use crate::util::require_root;
+
+pub fn run(napm: &mut Napm, pkg_names: &[&str], reason: PackageReason) -> Result<()> {
+    require_root()?;
+
+    napm.mark_pkgs(pkg_names, reason)
+}
@@ -0,0 +1,39 @@
+use crate::error::Result;
+use crate::ansi::*;
+use crate::napm::Napm;
+use crate::output::{OutputFormat, print_json, print_tsv};
+use crate::util::require_root;
+
+pub fn run(napm: &mut Napm, remove: bool, excludes: Vec<String>, optional: bool, format: OutputFormat) -> Result<()> {
+    if remove {
+        require_root()?;
+
+        return napm.remove_orphans(&excludes, optional);
+    }
+
+    let orphans = napm.orphans(optional, &[]);
+
+    match format {
+        OutputFormat::Text => {
+            for orphan in &orphans {
+                if orphan.optional_for.is_empty() {
+                    println!("{}", orphan.package.formatted_name(true));
+                } else {
+                    println!(
+                        "{} {ANSI_YELLOW}(optional for {}){ANSI_RESET}",
+                        orphan.package.formatted_name(true),
+                        orphan.optional_for.join(", "),
+                    );
+                }
+            }
+
+            Ok(())
+        }
+        OutputFormat::Json => print_json(&orphans),
+        OutputFormat::Tsv => print_tsv(
+            orphans
+                .iter()
+                .map(|o| [o.package.name.clone(), o.package.version.clone(), o.optional_for.join(",")])
+        ),
+    }
+}
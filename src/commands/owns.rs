@@ -0,0 +1,80 @@
+use serde::Serialize;
+use std::io::{self, BufRead};
+
+use crate::error::{Error, Result};
+use crate::ansi::*;
+use crate::napm::Napm;
+use crate::output::{OutputFormat, print_json, print_tsv};
+use crate::pkg::Pkg;
+
+#[derive(Serialize)]
+struct OwnsResult {
+    path: String,
+    owners: Vec<Pkg>,
+}
+
+/// Paths from the arguments, or one per line from stdin when there are none or the only one is `-`
+fn read_paths(paths: Vec<String>) -> Result<Vec<String>> {
+    if !paths.is_empty() && paths != ["-"] {
+        return Ok(paths);
+    }
+
+    let mut paths = Vec::new();
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let line = line.trim();
+
+        if !line.is_empty() {
+            paths.push(line.to_string());
+        }
+    }
+
+    Ok(paths)
+}
+
+pub fn run(napm: &Napm, paths: Vec<String>, format: OutputFormat) -> Result<()> {
+    let mut results = Vec::new();
+
+    for path in read_paths(paths)? {
+        let (path, owners) = napm.owners(&path)?;
+        results.push(OwnsResult { path, owners });
+    }
+
+    let not_owned = results.iter().filter(|r| r.owners.is_empty()).count();
+
+    match format {
+        OutputFormat::Text => {
+            for result in &results {
+                if result.owners.is_empty() {
+                    println!("{ANSI_BLUE}{}{ANSI_RESET} is not owned by any package", result.path);
+                }
+
+                for pkg in &result.owners {
+                    println!("{ANSI_BLUE}{}{ANSI_RESET} is owned by {}", result.path, pkg.formatted_name(true));
+                }
+            }
+        }
+        OutputFormat::Json => print_json(&results)?,
+        OutputFormat::Tsv => print_tsv(
+            results
+                .iter()
+                .flat_map(|r| {
+                    if r.owners.is_empty() {
+                        vec![[r.path.clone(), String::new(), String::new()]]
+                    } else {
+                        r.owners
+                            .iter()
+                            .map(|p| [r.path.clone(), p.name.clone(), p.version.clone()])
+                            .collect()
+                    }
+                })
+        )?,
+    }
+
+    if not_owned > 0 {
+        return Err(Error::NotOwned(not_owned));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,9 @@
+use crate::error::Result;
+use crate::napm::Napm;
+use crate::util::require_root;
+
+pub fn run(napm: &mut Napm, pkg_names: &[&str]) -> Result<()> {
+    require_root()?;
+
+    napm.reinstall(pkg_names)
+}
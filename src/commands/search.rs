@@ -1,9 +1,21 @@
+use serde::Serialize;
+
 use crate::error::Result;
 use crate::ansi::*;
 use crate::napm::Napm;
+use crate::napm::cache::SearchOptions;
+use crate::output::{OutputFormat, print_json, print_tsv};
+use crate::pkg::Pkg;
+
+#[derive(Serialize)]
+struct SearchResult<'a> {
+    rank: usize,
+    #[serde(flatten)]
+    package: &'a Pkg,
+}
 
-pub fn run(napm: &Napm, search_terms: Vec<String>, num_results: Option<u32>) -> Result<()> {
-    let results = napm.search(search_terms)?;
+pub fn run(napm: &Napm, search_terms: Vec<String>, num_results: Option<u32>, opts: SearchOptions, format: OutputFormat) -> Result<()> {
+    let results = napm.search(search_terms, &opts)?;
 
     let results = if let Some(n) = num_results {
         results.iter().take(n as usize).collect::<Vec<_>>()
@@ -11,14 +23,31 @@ pub fn run(napm: &Napm, search_terms: Vec<String>, num_results: Option<u32>) ->
         results.iter().collect::<Vec<_>>()
     };
 
-    for (i, pkg) in results.iter().enumerate().rev() {
-        println!(
-            " {ANSI_RED}-{ANSI_RESET} {ANSI_YELLOW}[{ANSI_BOLD}{}{ANSI_RESET}{ANSI_YELLOW}]{ANSI_RESET} {} {}",
-            i + 1,
-            pkg.formatted_name(true),
-            pkg.desc,
-        );
-    }
+    match format {
+        OutputFormat::Text => {
+            for (i, pkg) in results.iter().enumerate().rev() {
+                println!(
+                    " {ANSI_RED}-{ANSI_RESET} {ANSI_YELLOW}[{ANSI_BOLD}{}{ANSI_RESET}{ANSI_YELLOW}]{ANSI_RESET} {} {}",
+                    i + 1,
+                    pkg.formatted_name(true),
+                    pkg.desc,
+                );
+            }
 
-    Ok(())
+            Ok(())
+        }
+        OutputFormat::Json => print_json(
+            &results
+                .iter()
+                .enumerate()
+                .map(|(i, package)| SearchResult { rank: i + 1, package })
+                .collect::<Vec<_>>()
+        ),
+        OutputFormat::Tsv => print_tsv(
+            results
+                .iter()
+                .enumerate()
+                .map(|(i, p)| [(i + 1).to_string(), p.name.clone(), p.version.clone(), p.repo.clone(), p.desc.clone()])
+        ),
+    }
 }
@@ -35,12 +35,24 @@ pub enum Error {
     #[error("User denied required privilege escalation, please run {ANSI_YELLOW}{0}{ANSI_RESET}")]
     DeniedPE(String),
 
+    #[error("Cannot ask \"{0}\": stdin is not a terminal, use --noconfirm or --answer")]
+    NotInteractive(String),
+
+    #[error("Invalid answer `{0}`, expected QUESTION=yes|no (or a provider for select-provider)")]
+    AnswerParse(String),
+
     #[error("Stopped by the user")]
     Stopped,
 
     #[error("No results")]
     NoResults,
 
+    #[error("{0} path(s) are not owned by any package")]
+    NotOwned(usize),
+
+    #[error("{0} problem(s) found")]
+    CheckFailed(usize),
+
     #[error("Package {ANSI_YELLOW}{0}{ANSI_RESET} is already installed")]
     PackageAlreadyInstalled(String),
 
@@ -110,6 +122,15 @@ pub enum Error {
     #[error("Failed to parse `SigLevel = {0}` in the config")]
     SigLevelParse(String),
 
+    #[error("Failed to parse `Usage = {0}` in the config")]
+    UsageParse(String),
+
+    #[error("Failed to download {0}")]
+    Download(String),
+
+    #[error("XferCommand `{0}` failed")]
+    XferCommand(String),
+
     #[error("Failed to update")]
     Update,
 
@@ -119,6 +140,24 @@ pub enum Error {
     #[error("Failed to remove a package")]
     TransRemovePkg,
 
+    #[error("Package file {ANSI_YELLOW}{0}{ANSI_RESET} not found")]
+    PackageFileNotFound(String),
+
+    #[error("Failed to load {ANSI_YELLOW}{0}{ANSI_RESET}: {1}")]
+    LoadPkgFile(String, alpm::Error),
+
+    #[error("No cached package file for {ANSI_YELLOW}{0}{ANSI_RESET}")]
+    NoCachedVersions(String),
+
+    #[error("Failed to change the install reason of {ANSI_YELLOW}{0}{ANSI_RESET}")]
+    SetReason(String),
+
+    #[error("Invalid regular expression: {0}")]
+    InvalidRegex(String),
+
+    #[error("The cache was created by a newer napm (schema version {0}, this napm supports up to {1}), upgrade napm or remove the cache")]
+    CacheTooNew(i32, i32),
+
     #[error("Cache database error: {0}")]
     CacheDatabaseError(rusqlite::Error),
 }
@@ -1,4 +1,6 @@
+use alpm::PackageReason;
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 pub mod ansi;
 pub mod log;
@@ -6,13 +8,20 @@ pub mod error;
 pub mod pkg;
 pub mod util;
 pub mod napm;
+pub mod output;
 
 pub mod commands {
+    pub mod check;
+    pub mod downgrade;
     pub mod files;
     pub mod find;
     pub mod info;
     pub mod install;
     pub mod list;
+    pub mod mark;
+    pub mod orphans;
+    pub mod owns;
+    pub mod reinstall;
     pub mod remove;
     pub mod search;
     pub mod update;
@@ -20,18 +29,54 @@ pub mod commands {
 }
 
 use error::{Error, Result};
-use napm::Napm;
+use napm::{Napm, NapmOptions};
+use napm::cache::{FindMode, FindOptions, SearchOptions, SearchSort};
+use output::OutputFormat;
 
 #[derive(Parser)]
 #[command(name = "napm")]
 #[command(about = "napm - NeoArch Package Manager")]
 struct Cli {
+    #[arg(long, global = true, help = "Operate on an alternate installation root (e.g. a chroot or container)")]
+    root: Option<String>,
+
+    #[arg(long, global = true, help = "Use an alternate package database location")]
+    dbpath: Option<String>,
+
+    #[arg(long, global = true, help = "Use an alternate pacman config file")]
+    config: Option<String>,
+
+    #[arg(long, global = true, help = "Seconds to wait for a mirror to respond before trying the next one")]
+    download_timeout: Option<u64>,
+
+    #[arg(long, visible_alias = "yes", global = true, default_value_t = false, help = "Never ask, take the default (or policy) answer for every question")]
+    noconfirm: bool,
+
+    #[arg(long = "answer", value_name = "QUESTION=ANSWER", global = true, help = "Answer a question by policy, e.g. conflict=no, select-provider=0 (repeatable)")]
+    answers: Vec<String>,
+
+    #[arg(long, short, value_enum, global = true, default_value_t = OutputFormat::Text, help = "Output format of read-only commands (list, info, search, files, find, owns, check, orphans)")]
+    output: OutputFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    #[command(about = "Verify installed files against their packages (all packages when none given)")]
+    Check {
+        packages: Vec<String>,
+    },
+
+    #[command(about = "Install an older version of a package from the package cache")]
+    Downgrade {
+        package: String,
+
+        #[arg(long, value_name = "DIR", help = "Also look for package files in this directory")]
+        archive_dir: Option<PathBuf>,
+    },
+
     #[command(about = "List the files of a package")]
     Files {
         package: String,
@@ -44,8 +89,20 @@ enum Commands {
     Find {
         path: String,
 
-        #[arg(long, default_value_t = false, help = "Only match exact paths (e.g. /bin/sudo)")]
+        #[arg(long, default_value_t = false, conflicts_with_all = ["glob", "regex"], help = "Only match exact paths (e.g. /bin/sudo)")]
         exact: bool,
+
+        #[arg(long, default_value_t = false, conflicts_with = "regex", help = "Match the whole path against a shell pattern (e.g. '/usr/lib/libssl.so.*')")]
+        glob: bool,
+
+        #[arg(long, default_value_t = false, help = "Match the path against a regular expression")]
+        regex: bool,
+
+        #[arg(long = "repo", value_name = "REPO", help = "Only search these repositories (repeatable)")]
+        repos: Vec<String>,
+
+        #[arg(long, default_value_t = false, help = "Only show installed packages")]
+        installed: bool,
     },
 
     #[command(about = "Show package information")]
@@ -55,12 +112,55 @@ enum Commands {
 
     #[command(about = "Install packages")]
     Install {
+        #[arg(help = "Package names, package files or URLs")]
         packages: Vec<String>,
+
+        #[arg(long, default_value_t = false, conflicts_with = "asexplicit", help = "Mark the installed packages as dependencies")]
+        asdeps: bool,
+
+        #[arg(long, default_value_t = false, help = "Mark the installed packages as explicitly installed")]
+        asexplicit: bool,
     },
 
     #[command(about = "List installed packages")]
     List,
 
+    #[command(about = "Change the install reason of installed packages")]
+    Mark {
+        #[arg(required = true)]
+        packages: Vec<String>,
+
+        #[arg(long, default_value_t = false, conflicts_with = "asexplicit", required_unless_present = "asexplicit", help = "Mark as installed as a dependency")]
+        asdeps: bool,
+
+        #[arg(long, default_value_t = false, help = "Mark as explicitly installed")]
+        asexplicit: bool,
+    },
+
+    #[command(about = "List dependencies that are no longer required by any package")]
+    Orphans {
+        #[arg(long, default_value_t = false, help = "Remove the orphans")]
+        remove: bool,
+
+        #[arg(long = "exclude", value_name = "PACKAGE", help = "Never remove these packages, glob patterns allowed (repeatable)")]
+        excludes: Vec<String>,
+
+        #[arg(long, default_value_t = false, help = "Also include packages only optionally required by others, like pacman -Qdtt")]
+        optional: bool,
+    },
+
+    #[command(about = "Show which installed packages own files on disk")]
+    Owns {
+        #[arg(help = "Paths to look up, read from stdin (one per line) when omitted or `-`")]
+        paths: Vec<String>,
+    },
+
+    #[command(about = "Reinstall the installed version of packages")]
+    Reinstall {
+        #[arg(required = true)]
+        packages: Vec<String>,
+    },
+
     #[command(about = "Remove a package")]
     Remove {
         packages: Vec<String>,
@@ -75,6 +175,33 @@ enum Commands {
 
         #[arg(long, short)]
         num_results: Option<u32>,
+
+        #[arg(long = "repo", value_name = "REPO", help = "Only search these repositories (repeatable)")]
+        repos: Vec<String>,
+
+        #[arg(long, default_value_t = false, conflicts_with = "not_installed", help = "Only show installed packages")]
+        installed: bool,
+
+        #[arg(long, default_value_t = false, help = "Only show packages that are not installed")]
+        not_installed: bool,
+
+        #[arg(long, default_value_t = false, help = "Only show installed packages with a newer version available")]
+        upgradable: bool,
+
+        #[arg(long = "group", value_name = "GROUP", help = "Only show packages in one of these groups (repeatable)")]
+        groups: Vec<String>,
+
+        #[arg(long = "license", value_name = "LICENSE", help = "Only show packages with one of these licenses (repeatable)")]
+        licenses: Vec<String>,
+
+        #[arg(long, help = "Only show packages built for this architecture")]
+        arch: Option<String>,
+
+        #[arg(long, value_enum, default_value_t = SearchSort::Relevance)]
+        sort: SearchSort,
+
+        #[arg(long, default_value_t = false, help = "Treat the search terms as exact package names")]
+        exact_name: bool,
     },
 
     #[command(about = "Update the package metadata, NOTE: this is not a system upgrade !!!")]
@@ -92,13 +219,77 @@ enum CacheSubcommand {
 fn run() -> Result<()> {
     let cli = Cli::parse();
 
-    let mut napm = Napm::new()?;
+    util::set_no_confirm(cli.noconfirm);
+
+    let mut napm = Napm::new(NapmOptions {
+        root: cli.root,
+        dbpath: cli.dbpath,
+        config: cli.config,
+        download_timeout: cli.download_timeout,
+        no_confirm: cli.noconfirm,
+        answers: cli.answers,
+    })?;
 
     match cli.command {
         Commands::Update => commands::update::run(&mut napm),
-        Commands::Files { package, dirs } => commands::files::run(&mut napm, &package, dirs),
-        Commands::Info { package } => commands::info::run(&napm, &package),
-        Commands::Install { packages } => commands::install::run(
+        Commands::Files { package, dirs } => commands::files::run(&mut napm, &package, dirs, cli.output),
+        Commands::Downgrade { package, archive_dir } => commands::downgrade::run(&mut napm, &package, archive_dir),
+        Commands::Info { package } => commands::info::run(&napm, &package, cli.output),
+        Commands::Install { packages, asdeps, asexplicit } => commands::install::run(
+            &mut napm,
+            packages
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .as_slice(),
+            if asdeps {
+                Some(PackageReason::Depend)
+            } else if asexplicit {
+                Some(PackageReason::Explicit)
+            } else {
+                None
+            },
+        ),
+        Commands::List => commands::list::run(&napm, cli.output),
+        Commands::Find { path, exact, glob, regex, repos, installed } => commands::find::run(
+            &mut napm,
+            path,
+            FindOptions {
+                mode: if exact {
+                    FindMode::Exact
+                } else if glob {
+                    FindMode::Glob
+                } else if regex {
+                    FindMode::Regex
+                } else {
+                    FindMode::Suffix
+                },
+                repos,
+                installed,
+            },
+            cli.output,
+        ),
+        Commands::Check { packages } => commands::check::run(
+            &napm,
+            packages
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .as_slice(),
+            cli.output,
+        ),
+        Commands::Mark { packages, asdeps, .. } => commands::mark::run(
+            &mut napm,
+            packages
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .as_slice(),
+            if asdeps { PackageReason::Depend } else { PackageReason::Explicit },
+        ),
+        Commands::Orphans { remove, excludes, optional } => commands::orphans::run(&mut napm, remove, excludes, optional, cli.output),
+        Commands::Owns { paths } => commands::owns::run(&napm, paths, cli.output),
+        Commands::Reinstall { packages } => commands::reinstall::run(
             &mut napm,
             packages
                 .iter()
@@ -106,8 +297,6 @@ fn run() -> Result<()> {
                 .collect::<Vec<_>>()
                 .as_slice(),
         ),
-        Commands::List => commands::list::run(&napm),
-        Commands::Find { path, exact } => commands::find::run(&mut napm, path, exact),
         Commands::Remove { packages, no_deep } => commands::remove::run(
             &mut napm,
             packages
@@ -117,7 +306,34 @@ fn run() -> Result<()> {
                 .as_slice(),
             !no_deep,
         ),
-        Commands::Search { search_terms, num_results } => commands::search::run(&napm, search_terms, num_results),
+        Commands::Search {
+            search_terms,
+            num_results,
+            repos,
+            installed,
+            not_installed,
+            upgradable,
+            groups,
+            licenses,
+            arch,
+            sort,
+            exact_name,
+        } => commands::search::run(
+            &napm,
+            search_terms,
+            num_results,
+            SearchOptions {
+                repos,
+                installed: if installed { Some(true) } else if not_installed { Some(false) } else { None },
+                upgradable,
+                groups,
+                licenses,
+                arch,
+                sort,
+                exact_name,
+            },
+            cli.output,
+        ),
         Commands::Upgrade => commands::upgrade::run(&mut napm),
     }?;
 
@@ -1,25 +1,33 @@
 use alpm::{
     Alpm, AnyEvent, AnyQuestion, AnyDownloadEvent, DownloadEvent, DownloadEventCompleted,
-    DownloadEventProgress, DownloadResult, Usage,
+    DownloadEventProgress, DownloadResult, Progress,
 };
 use indicatif::{MultiProgress, ProgressBar};
 use pacmanconf::Config;
 use std::{
     collections::HashMap,
+    path::Path,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use crate::ansi::*;
 use crate::error::{Error, Result};
 use crate::pkg::Pkg;
 use crate::{log_info, log_warn, log_error};
-use crate::util::{confirm, choose};
+use crate::util::choose;
+use answers::{Answer, Answers, QuestionKind, NAPM_CONFIG_FILE};
+use fetch::{Fetcher, fetch_callback};
 
 pub mod actions;
+pub mod answers;
 pub mod auto_repair;
 pub mod util;
 pub mod style;
 pub mod cache;
+pub mod fetch;
+pub mod check;
+pub mod downgrade;
 
 // NAPM ERROR DATA
 
@@ -49,20 +57,73 @@ enum NapmErrorData {
     ConflictingDeps(Vec<NapmConflict>),
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct NapmOptions {
+    pub root: Option<String>,
+    pub dbpath: Option<String>,
+    pub config: Option<String>,
+    pub download_timeout: Option<u64>,
+    pub no_confirm: bool,
+    pub answers: Vec<String>,
+}
+
+impl NapmOptions {
+    /// Arguments to pass to a re-executed napm so it operates on the same tree
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        for (flag, value) in [
+            ("--root", &self.root),
+            ("--dbpath", &self.dbpath),
+            ("--config", &self.config),
+        ] {
+            if let Some(v) = value {
+                args.push(flag.to_string());
+                args.push(v.clone());
+            }
+        }
+
+        if let Some(timeout) = self.download_timeout {
+            args.push("--download-timeout".to_string());
+            args.push(timeout.to_string());
+        }
+
+        if self.no_confirm {
+            args.push("--noconfirm".to_string());
+        }
+
+        for answer in &self.answers {
+            args.push("--answer".to_string());
+            args.push(answer.clone());
+        }
+
+        args
+    }
+}
+
+/// First error raised while answering an alpm question, it cannot be returned from the callback
+type QuestionError = Arc<Mutex<Option<Error>>>;
+
 pub struct Napm {
     config: Config,
+    options: NapmOptions,
     handle: Option<Alpm>,
+    question_error: QuestionError,
 }
 
 impl Napm {
-    pub fn new() -> Result<Self> {
-        let cfg = Config::new().map_err(|_| Error::ConfigParse)?;
-
-        if cfg.root_dir != "/" {
-            unimplemented!("Non / root");
+    pub fn new(options: NapmOptions) -> Result<Self> {
+        let mut cfg = Config::with_opts::<&str>(
+            None,
+            options.config.as_deref(),
+            options.root.as_deref(),
+        ).map_err(|_| Error::ConfigParse)?;
+
+        if let Some(dbpath) = &options.dbpath {
+            cfg.db_path = dbpath.clone();
         }
 
-        let mut handle = Alpm::new("/", &cfg.db_path)?;
+        let mut handle = Alpm::new(cfg.root_dir.as_str(), cfg.db_path.as_str())?;
 
         let arch = cfg.architecture.first().map(String::as_str).unwrap();
 
@@ -77,12 +138,45 @@ impl Napm {
             handle.add_cachedir(path)?;
         }
 
+        for arch in &cfg.architecture {
+            handle.add_architecture(arch.as_str())?;
+        }
+
         handle.set_check_space(cfg.check_space);
+        handle.set_use_syslog(cfg.use_syslog);
+        handle.set_disable_dl_timeout(cfg.disable_download_timeout);
+
+        if !cfg.log_file.is_empty() {
+            handle.set_logfile(cfg.log_file.as_str())?;
+        }
 
         if cfg.parallel_downloads > 0 {
             handle.set_parallel_downloads(cfg.parallel_downloads as u32);
         }
 
+        handle.set_sandbox_user(cfg.download_user.as_deref())?;
+        handle.set_disable_sandbox_filesystem(cfg.disable_sandbox || cfg.disable_sandbox_filesystem);
+        handle.set_disable_sandbox_syscalls(cfg.disable_sandbox || cfg.disable_sandbox_syscalls);
+
+        for pkg in &cfg.ignore_pkg {
+            handle.add_ignorepkg(pkg.as_str())?;
+        }
+
+        for group in &cfg.ignore_group {
+            handle.add_ignoregroup(group.as_str())?;
+        }
+
+        for file in &cfg.no_upgrade {
+            handle.add_noupgrade(file.as_str())?;
+        }
+
+        for file in &cfg.no_extract {
+            handle.add_noextract(file.as_str())?;
+        }
+
+        let default_siglevel = Self::parse_siglevel(&cfg.sig_level)?;
+        handle.set_default_siglevel(default_siglevel)?;
+
         let local_siglevel = Self::parse_siglevel(&cfg.local_file_sig_level)?;
         let remote_siglevel = Self::parse_siglevel(&cfg.remote_file_sig_level)?;
 
@@ -104,10 +198,11 @@ impl Napm {
                 db.add_server(url)?;
             }
 
-            db.set_usage(Usage::all())?; // TODO? take from config
+            db.set_usage(Self::parse_usage(&repo.usage)?)?;
         }
 
-        handle.add_hookdir("/usr/share/libalpm/hooks")?;
+        let system_hook_dir = Path::new(&cfg.root_dir).join("usr/share/libalpm/hooks");
+        handle.add_hookdir(system_hook_dir.to_string_lossy().into_owned())?;
         
         for hook_dir in &cfg.hook_dir {
             handle.add_hookdir(hook_dir.clone())?;
@@ -119,20 +214,33 @@ impl Napm {
         // callbacks
 
         let download_progress = Arc::new(Mutex::new((MultiProgress::new(), HashMap::new())));
-        handle.set_dl_cb(download_progress, download_callback);
+        handle.set_dl_cb(download_progress.clone(), download_callback);
 
         handle.set_event_cb((), event_callback);
 
-        // let other_progress = Arc::new(Mutex::new((MultiProgress::new(), HashMap::new())));
-        // handle.set_progress_cb(other_progress, progress_callback);
+        let other_progress = Arc::new(Mutex::new((MultiProgress::new(), HashMap::new(), None)));
+        handle.set_progress_cb(other_progress, progress_callback);
 
-        handle.set_question_cb((), question_callback);
+        let answers = Answers::load(Path::new(NAPM_CONFIG_FILE), &options.answers)?;
+        let question_error = QuestionError::default();
+        handle.set_question_cb((answers, question_error.clone()), question_callback);
 
-        // TODO: handle.set_fetch_cb
+        let download_timeout = (!cfg.disable_download_timeout).then(|| {
+            Duration::from_secs(options.download_timeout.unwrap_or(fetch::DEFAULT_DOWNLOAD_TIMEOUT))
+        });
+
+        let fetcher = Fetcher::new(
+            (!cfg.xfer_command.is_empty()).then(|| cfg.xfer_command.clone()),
+            download_timeout,
+            download_progress,
+        );
+        handle.set_fetch_cb(fetcher, fetch_callback);
 
         Ok(Self {
             config: cfg,
+            options,
             handle: Some(handle),
+            question_error,
         })
     }
 }
@@ -207,12 +315,17 @@ fn event_callback(
     };
 }
 
+/// Answers alpm's questions, refusing when the user cannot be asked.
+/// Exiting here would leave the db lock behind, so the error is kept for `Napm::question_error`
 fn question_callback(
     q: AnyQuestion,
-    _: &mut (),
+    (answers, error): &mut (Answers, QuestionError),
 ) {
     use alpm::Question as Q;
-    use std::path::Path;
+
+    let confirm = |kind: QuestionKind, prompt: &str, default_yes: bool| {
+        answers.confirm_or_refuse(kind, prompt, default_yes, error)
+    };
 
     match q.question() {
         Q::Conflict(mut x) => {
@@ -220,20 +333,14 @@ fn question_callback(
             let pkg_b = x.conflict().package2().name();
             let prompt = format!("Conflict between {ANSI_CYAN}{pkg_a}{ANSI_RESET} and {ANSI_CYAN}{pkg_b}{ANSI_RESET}; Remove {ANSI_RED}{pkg_b}{ANSI_RESET}?");
 
-            match confirm(&prompt, true) {
-                Ok(ans) => x.set_remove(ans),
-                Err(err) => err.die(),
-            }
+            x.set_remove(confirm(QuestionKind::Conflict, &prompt, true));
         }
         Q::Replace(x) => {
             let old = x.oldpkg().name();
             let new = x.newpkg().name();
             let prompt = format!("Replace package {ANSI_CYAN}{old} with {ANSI_CYAN}{new}?");
 
-            match confirm(&prompt, true) {
-                Ok(ans) => x.set_replace(ans),
-                Err(err) => err.die(),
-            }
+            x.set_replace(confirm(QuestionKind::Replace, &prompt, true));
         }
         Q::Corrupted(mut x) => {
             let filepath = x.filepath();
@@ -241,20 +348,30 @@ fn question_callback(
             let reason = x.reason();
             let prompt = format!("File {ANSI_MAGENTA}{filename}{ANSI_RESET} is corrupted: {reason}. Remove package?");
 
-            match confirm(&prompt, true) {
-                Ok(ans) => x.set_remove(ans),
-                Err(err) => err.die(),
-            }
+            x.set_remove(confirm(QuestionKind::Corrupted, &prompt, true));
         }
         Q::ImportKey(mut x) => {
             let fingerprint = x.fingerprint();
             let name = x.uid();
             let prompt = format!("Import key {ANSI_BOLD}{fingerprint}{ANSI_RESET}, \"{name}\"?");
 
-            match confirm(&prompt, true) {
-                Ok(ans) => x.set_import(ans),
-                Err(err) => err.die(),
-            }
+            x.set_import(confirm(QuestionKind::ImportKey, &prompt, true));
+        }
+        Q::InstallIgnorepkg(mut x) => {
+            let name = x.pkg().name();
+            let prompt = format!("Package {ANSI_CYAN}{name}{ANSI_RESET} is in IgnorePkg/IgnoreGroup. Install anyway?");
+
+            x.set_install(confirm(QuestionKind::InstallIgnorePkg, &prompt, false));
+        }
+        Q::RemovePkgs(mut x) => {
+            let pkgs = x.packages()
+                .iter()
+                .map(|p| Pkg::format_name(p.name(), None))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let prompt = format!("Packages {pkgs} cannot be upgraded due to unresolvable dependencies. Skip them for this upgrade?");
+
+            x.set_skip(confirm(QuestionKind::RemovePkgs, &prompt, false));
         }
         Q::SelectProvider(mut x) => {
             let dep = x.depend();
@@ -267,12 +384,27 @@ fn question_callback(
 
             let prompt = format!("There are several providers for {ANSI_MAGENTA}{name}{ANSI_RESET} and you must choose one");
 
-            match choose(&prompt, providers.as_slice(), 0) {
-                Ok(ans) => x.set_index(ans),
-                Err(err) => err.die(),
+            let by_policy = match answers.get(QuestionKind::SelectProvider) {
+                Some(Answer::Choice(choice)) => choice
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&i| i < providers.len())
+                    .or_else(|| providers.iter().position(|p| p == choice)),
+                _ => None,
+            };
+
+            match by_policy {
+                Some(i) => {
+                    log_info!("{prompt} [{}, by policy]", providers[i]);
+                    x.set_index(i as i32);
+                }
+                // there is no safe provider to refuse with, the transaction fails anyway once the error is seen
+                None => x.set_index(choose(&prompt, providers.as_slice(), 0).unwrap_or_else(|err| {
+                    error.lock().unwrap().get_or_insert(err);
+                    0
+                })),
             }
         }
-        _ => (),
     }
 }
 
@@ -327,27 +459,76 @@ fn download_callback(
     }
 }
 
-// fn progress_callback(
-//     progress: Progress,
-//     file: &str,
-//     percent: i32,
-//     how_many: usize,
-//     current: usize,
-//     bars: &mut Arc<Mutex<(MultiProgress, HashMap<String, ProgressBar>)>>,
-// ) {
-//     let mut bars_guard = bars.lock().unwrap();
-//     let (mp, bar_map) = &mut *bars_guard;
-
-//     if let std::collections::hash_map::Entry::Vacant(e) = bar_map.entry(file.to_string()) {
-//         let pb = mp.add(ProgressBar::new(100));
-//         pb.set_style(Napm::progress_bar_style(false).clone());
-//         pb.set_message(file.to_string());
-
-//         e.insert(pb);
-//     }
-
-//     let pb: &mut ProgressBar = bar_map.get_mut(file).unwrap();
-
-//     pb.set_length(percent as u64);
-//     pb.set_message(format!("{file} {:?} {}/{}", progress, current, how_many));
-// }
+type ProgressBars = (MultiProgress, HashMap<String, ProgressBar>, Option<ProgressBar>);
+
+fn progress_callback(
+    progress: Progress,
+    pkg: &str,
+    percent: i32,
+    how_many: usize,
+    current: usize,
+    bars: &mut Arc<Mutex<ProgressBars>>,
+) {
+    let mut bars_guard = bars.lock().unwrap();
+    let (mp, bar_map, total_bar) = &mut *bars_guard;
+
+    let (action, is_pkg_op) = match progress {
+        Progress::AddStart => ("installing", true),
+        Progress::UpgradeStart => ("upgrading", true),
+        Progress::DowngradeStart => ("downgrading", true),
+        Progress::ReinstallStart => ("reinstalling", true),
+        Progress::RemoveStart => ("removing", true),
+        Progress::ConflictsStart => ("checking for file conflicts", false),
+        Progress::DiskspaceStart => ("checking available disk space", false),
+        Progress::IntegrityStart => ("checking package integrity", false),
+        Progress::LoadStart => ("loading package files", false),
+        Progress::KeyringStart => ("checking keys in keyring", false),
+    };
+
+    let message = if is_pkg_op {
+        format!("{action} {pkg}")
+    } else {
+        action.to_string()
+    };
+
+    if is_pkg_op && how_many > 0 {
+        let total = total_bar.get_or_insert_with(|| {
+            let pb = mp.add(ProgressBar::new(how_many as u64));
+            pb.set_style(Napm::progress_bar_total_style().clone());
+            pb.set_message("packages");
+            pb
+        });
+
+        total.set_length(how_many as u64);
+        total.set_position(if percent == 100 { current } else { current.saturating_sub(1) } as u64);
+    }
+
+    let key = format!("{progress:?} {pkg}");
+
+    let pb = bar_map.entry(key.clone()).or_insert_with(|| {
+        let pb = match total_bar {
+            Some(total) => mp.insert_before(total, ProgressBar::new(100)),
+            None => mp.add(ProgressBar::new(100)),
+        };
+        pb.set_style(Napm::progress_bar_style(false).clone());
+        pb
+    });
+
+    pb.set_position(percent.clamp(0, 100) as u64);
+
+    if is_pkg_op || how_many <= 1 {
+        pb.set_message(message.clone());
+    } else {
+        pb.set_message(format!("{message} ({current}/{how_many})"));
+    }
+
+    if percent >= 100 && (is_pkg_op || current >= how_many) {
+        if let Some(pb) = bar_map.remove(&key) {
+            pb.finish_with_message(format!("{message} done"));
+        }
+
+        if is_pkg_op && current >= how_many && let Some(total) = total_bar.take() {
+            total.finish_with_message("packages done");
+        }
+    }
+}
@@ -1,61 +1,191 @@
-use alpm::TransFlag;
+use alpm::{Alpm, TransFlag, Usage};
 
 use crate::napm::*;
+use crate::pkg::{Orphan, PkgFile};
+use crate::napm::cache::{FindMode, FindOptions};
 use crate::{log_info, log_warn, log_fatal};
+use crate::util::{confirm, format_size, glob_match};
+
+use alpm::PackageReason;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+struct TransSummaryRow {
+    action: &'static str,
+    name: String,
+    old_version: Option<String>,
+    new_version: Option<String>,
+    repo: String,
+    download_size: i64,
+    net_size: i64,
+}
 // use crate::util::require_root;
 
 impl Napm {
-    pub fn install_pkgs(&mut self, pkgs: &[Pkg]) -> Result<()> {
+    /// Resolves `install` targets given as paths, `file://` or remote URLs, the latter are downloaded into the cache dir
+    pub fn pkg_files(&self, targets: &[&str]) -> Result<Vec<PkgFile>> {
+        let mut files = Vec::new();
+
+        for target in targets {
+            if let Some(path) = target.strip_prefix("file://") {
+                files.push(PkgFile { path: path.to_string(), remote: false });
+            } else if target.contains("://") {
+                log_info!("Downloading {target}");
+
+                let fetched = self.h()
+                    .fetch_pkgurl([*target].iter())
+                    .map_err(|_| Error::Download(target.to_string()))?;
+
+                files.extend(fetched.iter().map(|path| PkgFile { path: path.to_string(), remote: true }));
+            } else {
+                files.push(PkgFile { path: target.to_string(), remote: false });
+            }
+        }
+
+        for file in &files {
+            if !Path::new(&file.path).is_file() {
+                return Err(Error::PackageFileNotFound(file.path.clone()));
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Installs sync packages and package files in one transaction,
+    /// `reason` overrides the install reason of every package in it, dependencies included
+    pub fn install_pkgs(&mut self, pkgs: &[Pkg], files: &[PkgFile], reason: Option<PackageReason>) -> Result<()> {
         log_info!("Installing {} with all {} dependencies",
             pkgs
                 .iter()
                 .map(|pkg| pkg.formatted_name(true))
+                .chain(files.iter().map(|file| file.path.clone()))
                 .collect::<Vec<_>>()
                 .join(", "),
-            if pkgs.len() == 1 { "its" } else { "their" }
+            if pkgs.len() + files.len() == 1 { "its" } else { "their" }
         );
 
+        self.trans_init(match reason {
+            Some(PackageReason::Depend) => TransFlag::ALL_DEPS,
+            Some(PackageReason::Explicit) => TransFlag::ALL_EXPLICIT,
+            None => TransFlag::NONE,
+        })?;
+
         {
-            let handle = self.handle.take().unwrap();
+            let mut handle = self.handle.take().unwrap();
 
-            let conflicts = handle.check_conflicts(
-                pkgs.iter()
-                    .map(|pkg| pkg.clone().into_package_ref(&handle))
-                    .collect::<std::result::Result<Vec<_>, _>>()?
-                    .iter(),
-            );
+            let res = Self::add_install_targets(&handle, pkgs, files);
+
+            if res.is_err() {
+                let _ = handle.trans_release();
+            }
 
             self.handle = Some(handle);
+            res?;
+        }
 
-            if !conflicts.is_empty() {
-                log_fatal!("Conflicts occured");
-                for c in conflicts {
-                    log_fatal!(" - {c:?}");
-                }
-                return Err(Error::Conflicts);
+        self.trans_prepare()?;
+
+        self.confirm_transaction("Proceed with installation?")?;
+
+        self.trans_commit()?;
+
+        Ok(())
+    }
+
+    /// Adds repo packages and package files to the transaction once none of them conflict,
+    /// files are loaded here since they only live as long as the handle they were loaded with
+    fn add_install_targets(handle: &Alpm, pkgs: &[Pkg], files: &[PkgFile]) -> Result<()> {
+        let repo_pkgs = pkgs
+            .iter()
+            .map(|pkg| pkg.clone().into_package_ref(handle))
+            .collect::<Result<Vec<_>>>()?;
+
+        let loaded = files
+            .iter()
+            .map(|file| {
+                let siglevel = if file.remote {
+                    handle.remote_file_siglevel()
+                } else {
+                    handle.local_file_siglevel()
+                };
+
+                handle
+                    .pkg_load(file.path.as_str(), true, siglevel)
+                    .map_err(|err| Error::LoadPkgFile(file.path.clone(), err))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut targets: Vec<&alpm::Pkg> = Vec::new();
+
+        for package in &repo_pkgs {
+            targets.push(package);
+        }
+
+        for package in &loaded {
+            targets.push(package);
+        }
+
+        let conflicts = handle.check_conflicts(targets.iter());
+
+        if !conflicts.is_empty() {
+            log_fatal!("Conflicts occured");
+            for c in conflicts {
+                log_fatal!(" - {c:?}");
             }
+            return Err(Error::Conflicts);
+        }
+
+        for package in repo_pkgs {
+            handle
+                .trans_add_pkg(package)
+                .map_err(|_| Error::TransAddPkg)?;
+        }
+
+        for package in loaded {
+            handle
+                .trans_add_pkg(package)
+                .map_err(|_| Error::TransAddPkg)?;
+        }
+
+        Ok(())
+    }
+
+    /// Changes the install reason of installed packages, like `pacman -D --asdeps/--asexplicit`
+    pub fn mark_pkgs(&mut self, pkg_names: &[&str], reason: PackageReason) -> Result<()> {
+        let localdb = self.h().localdb();
+
+        for name in pkg_names {
+            localdb.pkg(*name).map_err(|_| Error::PackageNotInLocalDb(name.to_string()))?;
         }
 
+        // there is no lock without a transaction, so hold one while writing to the local db
         self.trans_init(TransFlag::NONE)?;
 
-        {
-            let handle = self.handle.take().unwrap();
+        let res = pkg_names.iter().try_for_each(|name| {
+            let pkg = self.h().localdb().pkg(*name).map_err(|_| Error::PackageNotInLocalDb(name.to_string()))?;
+            let formatted = Pkg::format_name(name, None);
 
-            for pkg in pkgs {
-                let package = pkg.clone().into_package_ref(&handle)?;
-                handle
-                    .trans_add_pkg(package)
-                    .map_err(|_| Error::TransAddPkg)?;
+            let label = match reason {
+                PackageReason::Explicit => "explicitly installed",
+                PackageReason::Depend => "installed as a dependency",
+            };
+
+            if pkg.reason() == reason {
+                log_info!("{formatted} is already marked as {label}");
+                return Ok(());
             }
 
-            self.handle = Some(handle);
-        }
+            pkg.set_reason(reason).map_err(|_| Error::SetReason(name.to_string()))?;
+            log_info!("Marked {formatted} as {label}");
 
-        self.trans_prepare()?;
+            Ok(())
+        });
 
-        self.trans_commit()?;
+        self.trans_release()?;
 
-        Ok(())
+        res
     }
 
     pub fn upgrade(&mut self) -> Result<()> {
@@ -63,14 +193,24 @@ impl Napm {
 
         log_info!("Upgrading the system");
 
-        // TODO: list upgradable packages and ask for confimration
+        for (name, old_version, new_version) in self.ignored_upgrades() {
+            log_warn!(
+                "Ignoring upgrade of {} ({} => {}), it is listed in IgnorePkg/IgnoreGroup",
+                Pkg::format_name(&name, None),
+                old_version,
+                new_version,
+            );
+        }
 
         self.trans_init(TransFlag::NONE)?;
 
         self.h_mut().sync_sysupgrade(false)?;
+        self.question_error()?;
 
         self.trans_prepare()?;
 
+        self.confirm_transaction("Proceed with the upgrade?")?;
+
         self.trans_commit()
     }
 
@@ -109,11 +249,139 @@ impl Napm {
 
         self.trans_prepare()?;
 
+        let held = self
+            .h()
+            .trans_remove()
+            .iter()
+            .filter(|p| self.config.hold_pkg.iter().any(|hold| glob_match(hold, p.name())))
+            .map(|p| Pkg::format_name(p.name(), None))
+            .collect::<Vec<_>>();
+
+        if !held.is_empty() {
+            log_warn!("The following packages are listed in HoldPkg: {}", held.join(", "));
+
+            if !confirm("Do you really want to remove them?", false)? {
+                self.trans_release()?;
+                return Err(Error::Stopped);
+            }
+        }
+
+        self.confirm_transaction("Proceed with removal?")?;
+
         self.trans_commit()?;
 
         Ok(())
     }
 
+    fn transaction_summary(&self) -> Vec<TransSummaryRow> {
+        let handle = self.h();
+        let localdb = handle.localdb();
+
+        let added = handle.trans_add().iter().map(|pkg| {
+            let old = localdb.pkg(pkg.name()).ok();
+
+            let action = match old.map(|old| pkg.version().vercmp(old.version())) {
+                None => "install",
+                Some(Ordering::Greater) => "upgrade",
+                Some(Ordering::Less) => "downgrade",
+                Some(Ordering::Equal) => "reinstall",
+            };
+
+            TransSummaryRow {
+                action,
+                name: pkg.name().to_string(),
+                old_version: old.map(|p| p.version().to_string()),
+                new_version: Some(pkg.version().to_string()),
+                // package files have no db
+                repo: pkg.db().map(|db| db.name()).unwrap_or("file").to_string(),
+                download_size: pkg.download_size(),
+                net_size: pkg.isize() - old.map(|p| p.isize()).unwrap_or(0),
+            }
+        });
+
+        let removed = handle.trans_remove().iter().map(|pkg| TransSummaryRow {
+            action: "remove",
+            name: pkg.name().to_string(),
+            old_version: Some(pkg.version().to_string()),
+            new_version: None,
+            repo: "local".to_string(),
+            download_size: 0,
+            net_size: -pkg.isize(),
+        });
+
+        added.chain(removed).collect()
+    }
+
+    /// Prints what the prepared transaction will change and releases it if the user declines
+    fn confirm_transaction(&mut self, prompt: &str) -> Result<()> {
+        let rows = self.transaction_summary();
+
+        if rows.is_empty() {
+            self.trans_release()?;
+            return Err(Error::NothingToDo);
+        }
+
+        let headers = ["Action", "Package", "Old version", "New version", "Repo", "Download", "Net size"];
+
+        let cells = rows
+            .iter()
+            .map(|row| [
+                row.action.to_string(),
+                row.name.clone(),
+                row.old_version.clone().unwrap_or("-".to_string()),
+                row.new_version.clone().unwrap_or("-".to_string()),
+                row.repo.clone(),
+                format_size(row.download_size),
+                format_size(row.net_size),
+            ])
+            .collect::<Vec<_>>();
+
+        let widths = (0..headers.len())
+            .map(|i| cells.iter().map(|c| c[i].len()).chain(std::iter::once(headers[i].len())).max().unwrap_or(0))
+            .collect::<Vec<_>>();
+
+        log_info!("Transaction summary ({} packages):", rows.len());
+
+        eprintln!(
+            "  {ANSI_BOLD}{}{ANSI_RESET}",
+            headers
+                .iter()
+                .zip(&widths)
+                .map(|(h, w)| format!("{h:<w$}"))
+                .collect::<Vec<_>>()
+                .join("  ")
+        );
+
+        for c in &cells {
+            let color = match c[0].as_str() {
+                "install" | "upgrade" => ANSI_GREEN,
+                "downgrade" => ANSI_YELLOW,
+                "remove" => ANSI_RED,
+                _ => ANSI_RESET,
+            };
+
+            eprintln!(
+                "  {color}{:<w0$}{ANSI_RESET}  {ANSI_CYAN}{:<w1$}{ANSI_RESET}  {:<w2$}  {ANSI_MAGENTA}{:<w3$}{ANSI_RESET}  {:<w4$}  {:>w5$}  {:>w6$}",
+                c[0], c[1], c[2], c[3], c[4], c[5], c[6],
+                w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3],
+                w4 = widths[4], w5 = widths[5], w6 = widths[6],
+            );
+        }
+
+        let download_total: i64 = rows.iter().map(|r| r.download_size).sum();
+        let net_total: i64 = rows.iter().map(|r| r.net_size).sum();
+
+        log_info!("Total download size: {}", format_size(download_total));
+        log_info!("Net installed size:  {}", format_size(net_total));
+
+        if !confirm(prompt, true)? {
+            self.trans_release()?;
+            return Err(Error::Stopped);
+        }
+
+        Ok(())
+    }
+
     // pub fn search(&self, needles: &[&str]) -> Result<Vec<Pkg>> {
     //     let mut out = Vec::new();
 
@@ -124,14 +392,12 @@ impl Napm {
     //     Ok(out.into_iter().map(Pkg::from).collect())
     // }
 
-    pub fn find(&mut self, mut file: String, exact: bool) -> Result<Vec<(Pkg, String)>> {
-        file = if file.starts_with("/") {
-            file.to_owned()
-        } else {
-            format!("/{file}")
-        };
+    pub fn find(&mut self, mut file: String, opts: &FindOptions) -> Result<Vec<(Pkg, String)>> {
+        if matches!(opts.mode, FindMode::Suffix | FindMode::Exact) && !file.starts_with("/") {
+            file = format!("/{file}");
+        }
 
-        if exact {
+        if opts.mode == FindMode::Exact {
             for part in ["bin", "lib", "lib64", "sbin"] {
                 if file.starts_with(&format!("/{part}/")) {
                     file = format!("/usr{file}");
@@ -141,7 +407,180 @@ impl Napm {
             }
         }
 
-        self.find_packages_by_file(&file, exact)
+        self.find_packages_by_file(&file, opts)
+    }
+
+    /// Absolute path with every directory symlink resolved but the file itself kept, like `pacman -Qo`,
+    /// followed by the file's own target when it is a symlink
+    fn owned_path_candidates(path: &str) -> Result<Vec<PathBuf>> {
+        let abs = std::path::absolute(path)?;
+
+        let resolved = match (abs.parent(), abs.file_name()) {
+            (Some(parent), Some(name)) => fs::canonicalize(parent)
+                .map(|parent| parent.join(name))
+                .unwrap_or(abs.clone()),
+            _ => fs::canonicalize(&abs).unwrap_or(abs.clone()),
+        };
+
+        let mut candidates = vec![resolved.clone()];
+
+        if resolved.is_symlink() && let Ok(target) = fs::canonicalize(&resolved) {
+            candidates.push(target);
+        }
+
+        Ok(candidates)
+    }
+
+    /// Installed packages owning `path` on disk, together with the path that was looked up
+    pub fn owners(&self, path: &str) -> Result<(String, Vec<Pkg>)> {
+        let root = Path::new(&self.config.root_dir);
+        let candidates = Self::owned_path_candidates(path)?;
+
+        for candidate in &candidates {
+            let Ok(rel) = candidate.strip_prefix(root) else {
+                log_warn!("{} is outside of the root {}", candidate.display(), root.display());
+                continue;
+            };
+
+            // file lists are relative to the root, directories end with a slash
+            let mut key = rel.to_string_lossy().to_string();
+            if candidate.is_dir() && !candidate.is_symlink() {
+                key.push('/');
+            }
+
+            let owners: Vec<Pkg> = self.h()
+                .localdb()
+                .pkgs()
+                .iter()
+                .filter(|pkg| pkg.files().contains(key.as_str()).is_some())
+                .map(Pkg::from)
+                .collect();
+
+            if !owners.is_empty() {
+                return Ok((candidate.display().to_string(), owners));
+            }
+        }
+
+        Ok((candidates[0].display().to_string(), Vec::new()))
+    }
+
+    /// Dependencies required by nothing but other orphans, chains and cycles included;
+    /// with `include_optional` also those only optionally required by non-orphans.
+    /// Dependencies matching a `keep` pattern are never orphans, and neither is what they depend on
+    pub fn orphans(&self, include_optional: bool, keep: &[String]) -> Vec<Orphan> {
+        let localdb = self.h().localdb();
+
+        let deps: HashMap<String, (Vec<String>, Vec<String>)> = localdb
+            .pkgs()
+            .iter()
+            .filter(|pkg| pkg.reason() == PackageReason::Depend)
+            .filter(|pkg| !keep.iter().any(|pattern| glob_match(pattern, pkg.name())))
+            .map(|pkg| (
+                pkg.name().to_string(),
+                (pkg.required_by().into_iter().collect(), pkg.optional_for().into_iter().collect()),
+            ))
+            .collect();
+
+        // start from every dependency and drop those kept by a non-orphan until nothing changes
+        let fixpoint = |with_optional: bool| {
+            let mut orphans: HashSet<&str> = deps.keys().map(String::as_str).collect();
+
+            loop {
+                let kept: Vec<&str> = orphans
+                    .iter()
+                    .copied()
+                    .filter(|name| {
+                        let (required_by, optional_for) = &deps[*name];
+
+                        required_by
+                            .iter()
+                            .chain(optional_for.iter().filter(|_| with_optional))
+                            .any(|by| !orphans.contains(by.as_str()))
+                    })
+                    .collect();
+
+                if kept.is_empty() {
+                    return orphans;
+                }
+
+                for name in kept {
+                    orphans.remove(name);
+                }
+            }
+        };
+
+        let strict = fixpoint(true);
+        let orphans = if include_optional { fixpoint(false) } else { strict.clone() };
+
+        let mut orphans: Vec<Orphan> = orphans
+            .into_iter()
+            .filter_map(|name| localdb.pkg(name).ok())
+            .map(|pkg| Orphan {
+                package: Pkg::from(pkg),
+                optional_for: if strict.contains(pkg.name()) {
+                    Vec::new()
+                } else {
+                    deps[pkg.name()].1.clone()
+                },
+            })
+            .collect();
+
+        orphans.sort_by(|a, b| a.package.name.cmp(&b.package.name));
+
+        orphans
+    }
+
+    /// Removes the orphans except those matching `excludes` or HoldPkg, and the dependencies they need
+    pub fn remove_orphans(&mut self, excludes: &[String], include_optional: bool) -> Result<()> {
+        let keep: Vec<String> = excludes.iter().chain(&self.config.hold_pkg).cloned().collect();
+
+        let pkgs = self
+            .orphans(include_optional, &keep)
+            .into_iter()
+            .map(|orphan| orphan.package)
+            .collect::<Vec<_>>();
+
+        for orphan in self.orphans(include_optional, &[]) {
+            let pkg = &orphan.package;
+
+            if pkgs.iter().any(|p| p.name == pkg.name) {
+                continue;
+            }
+
+            if let Some(pattern) = excludes.iter().find(|pattern| glob_match(pattern, &pkg.name)) {
+                log_info!("Keeping {}, excluded by {pattern}", pkg.formatted_name(false));
+            } else if let Some(pattern) = self.config.hold_pkg.iter().find(|pattern| glob_match(pattern, &pkg.name)) {
+                log_info!("Keeping {}, listed in HoldPkg as {pattern}", pkg.formatted_name(false));
+            } else {
+                log_info!("Keeping {}, needed by a kept package", pkg.formatted_name(false));
+            }
+        }
+
+        if pkgs.is_empty() {
+            return Err(Error::NothingToDo);
+        }
+
+        self.remove_pkgs(&pkgs, false)
+    }
+
+    /// Installed packages with a newer sync version that IgnorePkg/IgnoreGroup holds back
+    pub fn ignored_upgrades(&self) -> Vec<(String, String, String)> {
+        let upgrade_dbs = self.syncdbs_with_usage(Usage::UPGRADE);
+
+        self.h()
+            .localdb()
+            .pkgs()
+            .iter()
+            .filter_map(|pkg| {
+                let new_pkg = pkg.sync_new_version(upgrade_dbs.iter().copied())?;
+
+                new_pkg.should_ignore().then(|| (
+                    pkg.name().to_string(),
+                    pkg.version().to_string(),
+                    new_pkg.version().to_string(),
+                ))
+            })
+            .collect()
     }
 
     pub fn list(&self) -> Vec<Pkg> {
@@ -0,0 +1,187 @@
+use cini::{Callback, CallbackKind, Ini};
+use std::{collections::HashMap, fs, path::Path, sync::Mutex};
+
+use crate::error::{Error, Result};
+use crate::log_info;
+use crate::util::confirm;
+
+pub const NAPM_CONFIG_FILE: &str = "/etc/napm.conf";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QuestionKind {
+    Conflict,
+    Replace,
+    Corrupted,
+    ImportKey,
+    SelectProvider,
+    RemovePkgs,
+    InstallIgnorePkg,
+}
+
+impl QuestionKind {
+    const ALL: &[QuestionKind] = &[
+        QuestionKind::Conflict,
+        QuestionKind::Replace,
+        QuestionKind::Corrupted,
+        QuestionKind::ImportKey,
+        QuestionKind::SelectProvider,
+        QuestionKind::RemovePkgs,
+        QuestionKind::InstallIgnorePkg,
+    ];
+
+    pub fn key(&self) -> &'static str {
+        match self {
+            QuestionKind::Conflict => "conflict",
+            QuestionKind::Replace => "replace",
+            QuestionKind::Corrupted => "corrupted",
+            QuestionKind::ImportKey => "import-key",
+            QuestionKind::SelectProvider => "select-provider",
+            QuestionKind::RemovePkgs => "remove-pkgs",
+            QuestionKind::InstallIgnorePkg => "install-ignorepkg",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        let key = key.trim().to_lowercase().replace('_', "-");
+        Self::ALL.iter().copied().find(|kind| kind.key() == key)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Answer {
+    Yes,
+    No,
+    /// Provider index or name, only valid for `select-provider`
+    Choice(String),
+}
+
+/// Policy answers for alpm questions, from `[answers]` in napm.conf and `--answer` overrides
+#[derive(Debug, Clone, Default)]
+pub struct Answers {
+    answers: HashMap<QuestionKind, Answer>,
+}
+
+impl Answers {
+    pub fn load(path: &Path, overrides: &[String]) -> Result<Self> {
+        let mut answers = Self::default();
+
+        if path.exists() {
+            let contents = fs::read_to_string(path)?;
+            answers.parse(path.to_str(), &contents)?;
+        }
+
+        for answer in overrides {
+            let (key, value) = answer
+                .split_once('=')
+                .ok_or(Error::AnswerParse(answer.clone()))?;
+
+            answers.set(key, value)?;
+        }
+
+        Ok(answers)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let invalid = || Error::AnswerParse(format!("{key}={value}"));
+
+        let kind = QuestionKind::from_key(key).ok_or_else(invalid)?;
+
+        let answer = match value.trim().to_lowercase().as_str() {
+            "y" | "yes" | "true" => Answer::Yes,
+            "n" | "no" | "false" => Answer::No,
+            other if kind == QuestionKind::SelectProvider && !other.is_empty() => {
+                Answer::Choice(value.trim().to_string())
+            }
+            _ => return Err(invalid()),
+        };
+
+        self.answers.insert(kind, answer);
+
+        Ok(())
+    }
+
+    pub fn get(&self, kind: QuestionKind) -> Option<&Answer> {
+        self.answers.get(&kind)
+    }
+
+    /// Answers a yes/no question from the policy, falling back to asking the user
+    pub fn confirm(&self, kind: QuestionKind, prompt: &str, default_yes: bool) -> Result<bool> {
+        match self.get(kind) {
+            Some(Answer::Yes) => {
+                log_info!("{prompt} [yes, by policy]");
+                Ok(true)
+            }
+            Some(Answer::No) => {
+                log_info!("{prompt} [no, by policy]");
+                Ok(false)
+            }
+            _ => confirm(prompt, default_yes),
+        }
+    }
+
+    /// Like `confirm`, but refuses whatever the default when the user cannot be asked (no import, no removal...),
+    /// the first such error is kept in `error` so the caller can fail the transaction once alpm returns
+    pub fn confirm_or_refuse(&self, kind: QuestionKind, prompt: &str, default_yes: bool, error: &Mutex<Option<Error>>) -> bool {
+        self.confirm(kind, prompt, default_yes).unwrap_or_else(|err| {
+            error.lock().unwrap().get_or_insert(err);
+            false
+        })
+    }
+}
+
+impl Ini for Answers {
+    type Err = Error;
+
+    fn callback(&mut self, cb: Callback) -> Result<()> {
+        match cb.kind {
+            CallbackKind::Section(_) => Ok(()),
+            CallbackKind::Directive(Some("answers"), key, Some(value)) => self.set(key, value),
+            CallbackKind::Directive(Some("answers"), key, None) => Err(Error::AnswerParse(key.to_string())),
+            CallbackKind::Directive(_, _, _) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn policy_answers_skip_the_prompt() {
+        let answers = Answers::load(Path::new("/nonexistent"), &["conflict=no".to_string(), "import-key=yes".to_string()]).unwrap();
+        let error = Mutex::new(None);
+
+        assert!(!answers.confirm_or_refuse(QuestionKind::Conflict, "remove?", true, &error));
+        assert!(answers.confirm_or_refuse(QuestionKind::ImportKey, "import?", false, &error));
+        assert!(error.lock().unwrap().is_none());
+    }
+
+    /// Runs itself again with stdin detached, the test harness may be attached to a terminal
+    #[test]
+    fn non_interactive_questions_are_refused() {
+        if std::env::var_os("NAPM_TEST_NO_TTY").is_none() {
+            let status = Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "napm::answers::tests::non_interactive_questions_are_refused", "--nocapture"])
+                .env("NAPM_TEST_NO_TTY", "1")
+                .stdin(Stdio::null())
+                .status()
+                .unwrap();
+
+            assert!(status.success());
+            return;
+        }
+
+        let answers = Answers::default();
+        let error = Mutex::new(None);
+
+        // refused even though the default is yes
+        assert!(!answers.confirm_or_refuse(QuestionKind::ImportKey, "import?", true, &error));
+        assert!(!answers.confirm_or_refuse(QuestionKind::Corrupted, "remove?", true, &error));
+
+        match &*error.lock().unwrap() {
+            Some(Error::NotInteractive(prompt)) => assert_eq!(prompt, "import?"),
+            other => panic!("expected NotInteractive, got {other:?}"),
+        }
+    }
+}
@@ -230,13 +230,16 @@ impl Napm {
         
         self.h_mut().set_dbext(dbext);
 
-        match self.h_mut().syncdbs_mut().update(false) {
+        let res = match self.h_mut().syncdbs_mut().update(false) {
             Err(e) => {
-                self.on_alpm_error(e, NapmErrorData::Empty)?;
-                self.h_mut().syncdbs_mut().update(false).map_err(|_| Error::Update)
+                self.on_alpm_error(e, NapmErrorData::Empty)
+                    .and_then(|_| self.h_mut().syncdbs_mut().update(false).map_err(|_| Error::Update))
             }
             Ok(b) => Ok(b),
-        }
+        };
+
+        self.question_error()?;
+        res
     }
 
     pub fn trans_init(&mut self, flags: TransFlag) -> Result<()> {
@@ -252,6 +255,12 @@ impl Napm {
     }
 
     pub fn trans_prepare(&mut self) -> Result<()> {
+        let res = self.try_trans_prepare();
+        self.question_error()?;
+        res
+    }
+
+    fn try_trans_prepare(&mut self) -> Result<()> {
         let (error, data) = {
             match self.h_mut().trans_prepare() {
                 Ok(()) => return Ok(()),
@@ -291,6 +300,12 @@ impl Napm {
     }
 
     pub fn trans_commit(&mut self) -> Result<()> {
+        let res = self.try_trans_commit();
+        self.question_error()?;
+        res
+    }
+
+    fn try_trans_commit(&mut self) -> Result<()> {
         let (error, data) = {
             match self.h_mut().trans_commit() {
                 Ok(()) => return Ok(()),
@@ -319,4 +334,20 @@ impl Napm {
         self.on_alpm_error(error, data)?;
         self.h_mut().trans_commit().map_err(|_| Error::TransCommit)
     }
-}
\ No newline at end of file
+
+    pub fn trans_release(&mut self) -> Result<()> {
+        self.h_mut().trans_release().map_err(|_| Error::TransRelease)
+    }
+
+    /// Returns the error a question callback ran into, releasing the transaction it happened in
+    pub fn question_error(&mut self) -> Result<()> {
+        let Some(err) = self.question_error.lock().unwrap().take() else {
+            return Ok(());
+        };
+
+        // there is no transaction when the question came from a database update
+        let _ = self.h_mut().trans_release();
+
+        Err(err)
+    }
+}
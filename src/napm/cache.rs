@@ -1,22 +1,124 @@
 use std::{
     fs,
     collections::{HashMap, HashSet},
-    io::Read,
-    path::Path,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
 };
-use flate2::read::GzDecoder;
+use flate2::read::MultiGzDecoder;
+use alpm::Usage;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use rusqlite::Connection;
+use regex::Regex;
+use rusqlite::{Connection, OpenFlags, functions::FunctionFlags, types::Value};
 use tar::Archive;
 
 use crate::error::{Error, Result};
 use crate::napm::*;
-use crate::log_warn;
-use crate::util::require_cache;
+use crate::pkg::{OptDep, PkgInfo};
+use crate::{log_info, log_warn};
+use crate::util;
+
+type ArchiveEntry<'a> = tar::Entry<'a, Box<dyn Read>>;
+
+/// Advances progress bars by the number of bytes read through it
+struct ProgressReader {
+    inner: fs::File,
+    bars: [ProgressBar; 2],
+}
+
+impl Read for ProgressReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+
+        for pb in &self.bars {
+            pb.inc(n as u64);
+        }
+
+        Ok(n)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CacheState {
+    Missing,
+    /// Interrupted first build, unreadable file or no completed update
+    Incomplete,
+    /// Older schema, migrated in place by the next update
+    Outdated,
+    Ready,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SearchSort {
+    /// Best matches first
+    #[default]
+    Relevance,
+    Name,
+    /// Largest installed size first
+    Size,
+    /// Most recently built first
+    Date,
+}
+
+/// Filters and ordering of `Napm::search`, all applied in SQL
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    pub repos: Vec<String>,
+    /// `Some(true)` for installed packages only, `Some(false)` for not installed ones only
+    pub installed: Option<bool>,
+    pub upgradable: bool,
+    pub groups: Vec<String>,
+    pub licenses: Vec<String>,
+    pub arch: Option<String>,
+    pub sort: SearchSort,
+    /// Terms are whole package names instead of full-text queries
+    pub exact_name: bool,
+}
+
+impl SearchOptions {
+    fn has_filters(&self) -> bool {
+        !self.repos.is_empty()
+            || self.installed.is_some()
+            || self.upgradable
+            || !self.groups.is_empty()
+            || !self.licenses.is_empty()
+            || self.arch.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FindMode {
+    /// Paths ending with the given one
+    #[default]
+    Suffix,
+    Exact,
+    /// Shell-style pattern over the whole path, see SQLite's `GLOB`
+    Glob,
+    /// Regular expression matched anywhere in the path
+    Regex,
+}
+
+/// Matching mode and filters of `Napm::find`
+#[derive(Debug, Clone, Default)]
+pub struct FindOptions {
+    pub mode: FindMode,
+    pub repos: Vec<String>,
+    /// Only packages that are installed
+    pub installed: bool,
+}
 
 pub const NAPM_CACHE_FILE: &str = "/var/cache/napm.sqlite";
 
+/// Bump together with a new entry in `Napm::CACHE_MIGRATIONS`
+pub const CACHE_SCHEMA_VERSION: i32 = 6;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
 impl Napm {
+    pub fn cache_path(&self) -> PathBuf {
+        Path::new(&self.config.root_dir).join(NAPM_CACHE_FILE.trim_start_matches('/'))
+    }
+
+    /// Creates the original (version 1) schema, `migrate_cache` brings it up to date
     fn init_cache_schema(conn: &Connection) -> Result<()> {
         conn.execute(
             "
@@ -68,6 +170,405 @@ impl Napm {
         Ok(())
     }
 
+    /// Full desc metadata: extra `package_desc` columns and the depends, groups and licenses tables
+    fn migrate_cache_v1_to_v2(conn: &Connection) -> Result<()> {
+        for column in [
+            "base TEXT",
+            "arch TEXT",
+            "url TEXT",
+            "packager TEXT",
+            "build_date INTEGER",
+            "download_size INTEGER",
+            "installed_size INTEGER",
+        ] {
+            conn.execute(&format!("ALTER TABLE package_desc ADD COLUMN {column}"), ())?;
+        }
+
+        conn.execute(
+            "
+            CREATE TABLE package_depends (
+                repo TEXT NOT NULL,
+                name TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                target TEXT NOT NULL,
+                dep TEXT NOT NULL,
+                desc TEXT
+            );
+            ",
+            (),
+        )?;
+
+        conn.execute(
+            "
+            CREATE TABLE package_groups (
+                repo TEXT NOT NULL,
+                name TEXT NOT NULL,
+                grp TEXT NOT NULL
+            );
+            ",
+            (),
+        )?;
+
+        conn.execute(
+            "
+            CREATE TABLE package_licenses (
+                repo TEXT NOT NULL,
+                name TEXT NOT NULL,
+                license TEXT NOT NULL
+            );
+            ",
+            (),
+        )?;
+
+        conn.execute(
+            "
+            CREATE INDEX idx_package_depends_repo_name ON package_depends(repo, name);
+            ",
+            (),
+        )?;
+
+        conn.execute(
+            "
+            CREATE INDEX idx_package_depends_kind_target ON package_depends(kind, target);
+            ",
+            (),
+        )?;
+
+        conn.execute(
+            "
+            CREATE INDEX idx_package_groups_repo_name ON package_groups(repo, name);
+            ",
+            (),
+        )?;
+
+        conn.execute(
+            "
+            CREATE INDEX idx_package_groups_grp ON package_groups(grp);
+            ",
+            (),
+        )?;
+
+        conn.execute(
+            "
+            CREATE INDEX idx_package_licenses_repo_name ON package_licenses(repo, name);
+            ",
+            (),
+        )?;
+
+        // existing rows lack the new metadata, have the next update index them again
+        conn.execute("UPDATE package_desc SET files_done = false", ())?;
+
+        Ok(())
+    }
+
+    /// `cache_meta` key-value table, `last_update` marks a cache whose last update ran to completion
+    fn migrate_cache_v2_to_v3(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "
+            CREATE TABLE cache_meta (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+            ",
+            (),
+        )?;
+
+        Ok(())
+    }
+
+    /// `package_fts` full-text index over names, descriptions, provides and groups,
+    /// its rowids follow `package_desc` rowids
+    fn migrate_cache_v3_to_v4(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "
+            CREATE VIRTUAL TABLE package_fts USING fts5(
+                repo UNINDEXED,
+                name,
+                desc,
+                provides,
+                groups,
+                prefix = '2 3'
+            );
+            ",
+            (),
+        )?;
+
+        conn.execute(
+            "
+            INSERT INTO package_fts (rowid, repo, name, desc, provides, groups)
+            SELECT
+                d.rowid,
+                d.repo,
+                d.name,
+                d.desc,
+                (
+                    SELECT group_concat(target, ' ')
+                    FROM package_depends AS p
+                    WHERE p.repo = d.repo AND p.name = d.name AND p.kind = 'provides'
+                ),
+                (
+                    SELECT group_concat(grp, ' ')
+                    FROM package_groups AS g
+                    WHERE g.repo = d.repo AND g.name = d.name
+                )
+            FROM package_desc AS d
+            ",
+            (),
+        )?;
+
+        Ok(())
+    }
+
+    /// `fuzzy_words` vocabulary of name and description words and its `fuzzy_trigrams` index,
+    /// filled by the next update
+    fn migrate_cache_v4_to_v5(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "
+            CREATE TABLE fuzzy_words (
+                word TEXT PRIMARY KEY,
+                len INTEGER NOT NULL
+            );
+            ",
+            (),
+        )?;
+
+        conn.execute(
+            "
+            CREATE TABLE fuzzy_trigrams (
+                trigram TEXT NOT NULL,
+                word TEXT NOT NULL
+            );
+            ",
+            (),
+        )?;
+
+        conn.execute(
+            "
+            CREATE INDEX idx_fuzzy_trigrams_trigram ON fuzzy_trigrams(trigram);
+            ",
+            (),
+        )?;
+
+        Ok(())
+    }
+
+    /// `package_name_trigram` index of names for substring matches, its rowids follow `package_desc` rowids
+    fn migrate_cache_v5_to_v6(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "
+            CREATE VIRTUAL TABLE package_name_trigram USING fts5(
+                name,
+                tokenize = 'trigram'
+            );
+            ",
+            (),
+        )?;
+
+        conn.execute(
+            "
+            INSERT INTO package_name_trigram (rowid, name)
+            SELECT rowid, name FROM package_desc
+            ",
+            (),
+        )?;
+
+        Ok(())
+    }
+
+    /// `CACHE_MIGRATIONS[i]` upgrades a cache from schema version `i + 1` to `i + 2`
+    const CACHE_MIGRATIONS: &[fn(&Connection) -> Result<()>] = &[
+        Self::migrate_cache_v1_to_v2,
+        Self::migrate_cache_v2_to_v3,
+        Self::migrate_cache_v3_to_v4,
+        Self::migrate_cache_v4_to_v5,
+        Self::migrate_cache_v5_to_v6,
+    ];
+
+    /// Schema version stored in `PRAGMA user_version`, caches from before versioning are version 1
+    /// and an empty database is version 0
+    fn cache_schema_version(conn: &Connection) -> Result<i32> {
+        let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        if version == 0 && conn
+            .prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'package_desc'")?
+            .exists([])?
+        {
+            return Ok(1);
+        }
+
+        Ok(version)
+    }
+
+    fn check_cache_schema_version(version: i32) -> Result<()> {
+        if version > CACHE_SCHEMA_VERSION {
+            return Err(Error::CacheTooNew(version, CACHE_SCHEMA_VERSION));
+        }
+
+        Ok(())
+    }
+
+    /// Creates or upgrades the cache schema in place, in a single transaction
+    fn migrate_cache(conn: &mut Connection) -> Result<()> {
+        let version = Self::cache_schema_version(conn)?;
+        Self::check_cache_schema_version(version)?;
+
+        if version == CACHE_SCHEMA_VERSION {
+            return Ok(());
+        }
+
+        let tx = conn.transaction()?;
+
+        if version == 0 {
+            log_warn!("Creating the cache from scratch, this will take some time...");
+            Self::init_cache_schema(&tx)?;
+        } else {
+            log_warn!("Migrating the cache from schema version {version} to {CACHE_SCHEMA_VERSION}, this will take some time...");
+        }
+
+        for migration in &Self::CACHE_MIGRATIONS[(version.max(1) - 1) as usize..] {
+            migration(&tx)?;
+        }
+
+        tx.pragma_update(None, "user_version", CACHE_SCHEMA_VERSION)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn cache_state(cache_path: &Path) -> Result<CacheState> {
+        if !cache_path.exists() {
+            return Ok(CacheState::Missing);
+        }
+
+        let Ok(conn) = Connection::open_with_flags(cache_path, OpenFlags::SQLITE_OPEN_READ_ONLY) else {
+            return Ok(CacheState::Incomplete);
+        };
+
+        // not a database at all, e.g. a build that was interrupted before the first commit
+        let Ok(version) = Self::cache_schema_version(&conn) else {
+            return Ok(CacheState::Incomplete);
+        };
+
+        Self::check_cache_schema_version(version)?;
+
+        if version == 0 {
+            return Ok(CacheState::Incomplete);
+        }
+
+        if version < CACHE_SCHEMA_VERSION {
+            return Ok(CacheState::Outdated);
+        }
+
+        let complete = conn
+            .prepare("SELECT 1 FROM cache_meta WHERE key = 'last_update'")?
+            .exists([])?;
+
+        Ok(if complete { CacheState::Ready } else { CacheState::Incomplete })
+    }
+
+    /// Opens the cache for reading, updating it first if it is missing, incomplete or from an older napm
+    fn open_cache(&self) -> Result<Connection> {
+        let cache_path = self.cache_path();
+
+        let outdated = match Self::cache_state(&cache_path)? {
+            CacheState::Ready | CacheState::Missing => false,
+            CacheState::Outdated => {
+                log_warn!("The cache was created by an older napm and needs to be migrated");
+                true
+            }
+            CacheState::Incomplete => {
+                log_warn!("The cache is incomplete, its last update was probably interrupted");
+                true
+            }
+        };
+
+        let mut update_args = self.options.to_args();
+        update_args.push("update".to_string());
+
+        util::require_cache(&cache_path, outdated, update_args)?;
+
+        let conn = Connection::open_with_flags(&cache_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        Self::register_cache_functions(&conn)?;
+
+        Ok(conn)
+    }
+
+    /// SQL functions available to cache queries
+    fn register_cache_functions(conn: &Connection) -> Result<()> {
+        conn.create_scalar_function(
+            "vercmp",
+            2,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| {
+                let a: String = ctx.get(0)?;
+                let b: String = ctx.get(1)?;
+
+                Ok(alpm::vercmp(a, b) as i32)
+            },
+        )?;
+
+        // backs `text REGEXP pattern`, the compiled pattern is kept for the whole statement
+        conn.create_scalar_function(
+            "regexp",
+            2,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| {
+                let re = ctx.get_or_create_aux(0, |vr| -> std::result::Result<_, BoxError> {
+                    Ok(Regex::new(vr.as_str()?)?)
+                })?;
+
+                let text = ctx.get_raw(1).as_str().map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+
+                Ok(re.is_match(text))
+            },
+        )?;
+
+        Ok(())
+    }
+
+    /// Opens the cache for an in-place update, in WAL mode so readers are never blocked by it
+    fn open_cache_for_writing(cache_path: &Path) -> Result<Connection> {
+        let conn = Connection::open(cache_path)?;
+
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+
+        // keep `-wal` and `-shm` after closing, unprivileged readers cannot create them
+        // in the cache directory and would fail to open the cache without them
+        let mut persist: std::ffi::c_int = 1;
+        unsafe {
+            rusqlite::ffi::sqlite3_file_control(
+                conn.handle(),
+                c"main".as_ptr(),
+                rusqlite::ffi::SQLITE_FCNTL_PERSIST_WAL,
+                (&mut persist as *mut std::ffi::c_int).cast(),
+            );
+        }
+
+        Ok(conn)
+    }
+
+    /// Exclusive advisory lock serializing cache updates, held until the returned file is dropped
+    fn lock_cache(cache_path: &Path) -> Result<fs::File> {
+        let lock_path = cache_path.with_extension("sqlite.lock");
+
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&lock_path)?;
+
+        match file.try_lock() {
+            Ok(()) => (),
+            Err(fs::TryLockError::WouldBlock) => {
+                log_warn!("Waiting for another napm to finish updating the cache...");
+                file.lock()?;
+            }
+            Err(fs::TryLockError::Error(err)) => return Err(err.into()),
+        }
+
+        Ok(file)
+    }
+
     fn repo_priority(&self) -> String {
         self.repo_priority_with_column_name("repo")
     }
@@ -76,212 +577,608 @@ impl Napm {
         format!("CASE {col_name} {} ELSE 1000 END", self.config.repos.iter().enumerate().map(|(i, r)| format!("WHEN '{}' THEN {}", r.name, i)).collect::<Vec<_>>().join(" "))
     }
 
-    fn pkg_exists(conn: &Connection, pkg_name: &str) -> Result<bool> {
-        Ok(conn.prepare("SELECT 1 FROM package_desc WHERE name = ?1")?.exists([pkg_name])?)
+    /// SQL condition restricting `col_name` to repos whose `Usage` allows `usage`
+    fn repo_usage_filter(&self, col_name: &str, usage: Usage) -> String {
+        format!("{col_name} IN ({})", self.syncdbs_with_usage(usage).iter().map(|db| format!("'{}'", db.name())).collect::<Vec<_>>().join(", "))
     }
 
-    fn count_archive_files(path: &Path) -> Result<usize> {
-        let file = fs::File::open(path).map_err(|_| Error::OpenArchive)?;
-        let decoder = GzDecoder::new(file);
-        let mut archive = Archive::new(decoder);
-        Ok(archive.entries().map_err(|_| Error::ExtractArchive)?.into_iter().count())
+    fn pkg_exists(conn: &Connection, pkg_name: &str) -> Result<bool> {
+        Ok(conn.prepare("SELECT 1 FROM package_desc WHERE name = ?1")?.exists([pkg_name])?)
     }
 
+    /// Streams the archive once, calling `f` for every regular file, progress follows the compressed bytes read
     fn process_archive<F>(
         mp: &MultiProgress,
         total_pb: &ProgressBar,
         path: &Path,
         repo: &str,
-        action: &str,
         mut f: F
     ) -> Result<()>
     where
-        F: FnMut(&mut tar::Entry<GzDecoder<fs::File>>) -> Result<()>,
+        F: FnMut(&mut ArchiveEntry) -> Result<()>,
     {
         let file = fs::File::open(path).map_err(|_| Error::OpenArchive)?;
-        let decoder = GzDecoder::new(file);
-        let mut archive = Archive::new(decoder);
+        let len = file.metadata()?.len();
 
-        let len = Self::count_archive_files(path)?;
+        let pb = mp.insert_before(total_pb, ProgressBar::new(len));
+        pb.set_style(
+            ProgressStyle::with_template("[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% {msg} {bytes}/{total_bytes}")
+                .unwrap()
+                .progress_chars("=> ")
+        );
+        pb.set_message(format!("caching {repo}..."));
+
+        let reader = ProgressReader {
+            inner: file,
+            bars: [pb.clone(), total_pb.clone()],
+        };
+
+        let mut archive = Archive::new(Self::decompress(reader)?);
+
+        for entry in archive.entries().map_err(|_| Error::ExtractArchive)? {
+            let mut entry = entry.map_err(|_| Error::ExtractArchive)?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            f(&mut entry)?;
+        }
 
-        let pb = mp.insert_before(total_pb, ProgressBar::new(len as u64));
         pb.set_style(
-            ProgressStyle::with_template("[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% {msg} {pos}/{len}")
+            ProgressStyle::with_template("[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% {msg}")
                 .unwrap()
                 .progress_chars("=> ")
         );
-        pb.set_message(format!("caching {repo}: {action}..."));
-        pb.set_length(Self::count_archive_files(path)? as u64);
+        pb.finish_with_message(format!("caching {repo} done"));
+
+        Ok(())
+    }
+
+    /// Wraps the database in the decoder matching its magic bytes, repo-add can write any of these
+    fn decompress(reader: ProgressReader) -> Result<Box<dyn Read>> {
+        let mut reader = BufReader::new(reader);
+        let magic = reader.fill_buf()?;
+
+        let decoder: Box<dyn Read> = if magic.starts_with(&[0x1f, 0x8b]) {
+            Box::new(MultiGzDecoder::new(reader))
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Box::new(zstd::stream::read::Decoder::with_buffer(reader)?)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Box::new(xz2::read::XzDecoder::new_multi_decoder(reader))
+        } else if magic.starts_with(b"BZh") {
+            Box::new(bzip2::read::MultiBzDecoder::new(reader))
+        } else {
+            // uncompressed tar
+            Box::new(reader)
+        };
+
+        Ok(decoder)
+    }
+
+    fn parse_entry_path(entry: &ArchiveEntry) -> Result<(String, String)> {
+        let path = entry.path().map_err(|_| Error::ExtractArchive)?;
+        let parts: Vec<_> = path.iter().map(|os| os.to_string_lossy()).collect();
+        if parts.len() < 2 {
+            return Err(Error::ExtractArchive);
+        }
+        Ok((parts[0].to_string(), parts[1].to_string()))
+    }
+
+    fn read_entry(entry: &mut ArchiveEntry) -> Result<String> {
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+
+        String::from_utf8(contents).map_err(|_| Error::ExtractArchive)
+    }
+
+    /// Parses a `desc` file into `%TAG%` => values, list fields span several lines
+    fn parse_desc(contents: &str) -> HashMap<String, Vec<String>> {
+        let mut fields = HashMap::new();
+        let mut lines = contents.lines();
+
+        while let Some(line) = lines.next() {
+            let Some(tag) = line.strip_prefix('%').and_then(|l| l.strip_suffix('%')) else {
+                continue;
+            };
+
+            let values = lines
+                .by_ref()
+                .take_while(|l| !l.is_empty())
+                .map(str::to_string)
+                .collect();
+
+            fields.insert(tag.to_string(), values);
+        }
+
+        fields
+    }
+
+    /// Splits `name>=1.0: description` into the name, the dependency without description and the description
+    fn parse_dep(dep: &str) -> (String, String, Option<String>) {
+        let (dep, desc) = match dep.split_once(": ") {
+            Some((dep, desc)) => (dep, Some(desc.to_string())),
+            None => (dep, None),
+        };
+
+        let target = dep
+            .split(['<', '>', '='])
+            .next()
+            .unwrap_or(dep)
+            .to_string();
+
+        (target, dep.to_string(), desc)
+    }
+
+    fn cache_pkg_desc(conn: &Connection, repo: &str, desc: &HashMap<String, Vec<String>>) -> Result<String> {
+        let field = |tag: &str| desc.get(tag).and_then(|v| v.first()).cloned();
+        let list = |tag: &str| desc.get(tag).cloned().unwrap_or_default();
+        let number = |tag: &str| field(tag).and_then(|v| v.parse::<i64>().ok());
+
+        let name = field("NAME").ok_or(Error::ExtractArchive)?;
+        let version = field("VERSION").ok_or(Error::ExtractArchive)?;
+
+        for table in Self::CACHE_ROWID_TABLES {
+            conn.prepare_cached(&format!("DELETE FROM {table} WHERE rowid = (SELECT rowid FROM package_desc WHERE repo = ?1 AND name = ?2)"))?
+                .execute((repo, &name))?;
+        }
+
+        conn.prepare_cached(
+            "
+            INSERT OR REPLACE INTO package_desc (
+                name, version, desc, repo, files_done,
+                base, arch, url, packager, build_date, download_size, installed_size
+            ) VALUES (?1, ?2, ?3, ?4, false, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            ",
+        )?.execute(
+            rusqlite::params![
+                &name,
+                &version,
+                &list("DESC").join("\n"),
+                repo,
+                field("BASE"),
+                field("ARCH"),
+                field("URL"),
+                field("PACKAGER"),
+                number("BUILDDATE"),
+                number("CSIZE"),
+                number("ISIZE"),
+            ],
+        )?;
+
+        let rowid = conn.last_insert_rowid();
+
+        for table in ["package_depends", "package_groups", "package_licenses"] {
+            conn.prepare_cached(&format!("DELETE FROM {table} WHERE repo = ?1 AND name = ?2"))?
+                .execute((repo, &name))?;
+        }
+
+        for (tag, kind) in [
+            ("DEPENDS", "depends"),
+            ("OPTDEPENDS", "optdepends"),
+            ("MAKEDEPENDS", "makedepends"),
+            ("CHECKDEPENDS", "checkdepends"),
+            ("PROVIDES", "provides"),
+            ("CONFLICTS", "conflicts"),
+            ("REPLACES", "replaces"),
+        ] {
+            for dep in list(tag) {
+                let (target, dep, dep_desc) = Self::parse_dep(&dep);
+
+                conn.prepare_cached("INSERT INTO package_depends (repo, name, kind, target, dep, desc) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?
+                    .execute((repo, &name, kind, &target, &dep, &dep_desc))?;
+            }
+        }
+
+        for group in list("GROUPS") {
+            conn.prepare_cached("INSERT INTO package_groups (repo, name, grp) VALUES (?1, ?2, ?3)")?
+                .execute((repo, &name, &group))?;
+        }
+
+        for license in list("LICENSE") {
+            conn.prepare_cached("INSERT INTO package_licenses (repo, name, license) VALUES (?1, ?2, ?3)")?
+                .execute((repo, &name, &license))?;
+        }
+
+        let provides = list("PROVIDES")
+            .iter()
+            .map(|dep| Self::parse_dep(dep).0)
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        conn.prepare_cached("INSERT INTO package_fts (rowid, repo, name, desc, provides, groups) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?
+            .execute((rowid, repo, &name, &list("DESC").join("\n"), &provides, &list("GROUPS").join(" ")))?;
+
+        conn.prepare_cached("INSERT INTO package_name_trigram (rowid, name) VALUES (?1, ?2)")?
+            .execute((rowid, &name))?;
+
+        Ok(name)
+    }
+
+    /// Stores a package, `files` is the raw `files` entry of its archive directory if there is one
+    fn cache_pkg(conn: &Connection, repo: &str, desc: &HashMap<String, Vec<String>>, files: Option<&str>) -> Result<()> {
+        let name = Self::cache_pkg_desc(conn, repo, desc)?;
+
+        let Some(files) = files else {
+            return Ok(());
+        };
+
+        conn.prepare_cached("DELETE FROM package_files WHERE repo = ?1 AND name = ?2")?
+            .execute((repo, &name))?;
+
+        let mut insert = conn.prepare_cached("INSERT INTO package_files (repo, name, path) VALUES (?1, ?2, ?3)")?;
+
+        for line in files.lines().skip(1) {
+            insert.execute((repo, &name, line))?;
+        }
+
+        conn.prepare_cached("UPDATE package_desc SET files_done = true WHERE repo = ?1 AND name = ?2")?
+            .execute((repo, &name))?;
+
+        Ok(())
+    }
+
+    /// Lookup-only indexes, dropped during a bulk load and created once all rows are in;
+    /// the `(repo, name)` ones stay since the load itself deletes by them
+    const CACHE_LOOKUP_INDEXES: &[(&str, &str)] = &[
+        ("idx_package_depends_kind_target", "package_depends(kind, target)"),
+        ("idx_package_groups_grp", "package_groups(grp)"),
+        ("idx_package_files_name", "package_files(name)"),
+        ("idx_package_files_path", "package_files(path)"),
+    ];
+
+    fn drop_cache_lookup_indexes(conn: &Connection) -> Result<()> {
+        for (name, _) in Self::CACHE_LOOKUP_INDEXES {
+            conn.execute(&format!("DROP INDEX IF EXISTS {name}"), ())?;
+        }
+
+        Ok(())
+    }
+
+    fn create_cache_lookup_indexes(conn: &Connection) -> Result<()> {
+        for (name, on) in Self::CACHE_LOOKUP_INDEXES {
+            conn.execute(&format!("CREATE INDEX IF NOT EXISTS {name} ON {on}"), ())?;
+        }
+
+        Ok(())
+    }
+
+    /// Configured sync repositories together with their `.files` archive, repos without one are skipped
+    fn repo_archives(&self) -> Vec<(String, PathBuf)> {
+        let sync_dir = Path::new(self.h().dbpath()).join("sync");
+
+        self.h()
+            .syncdbs()
+            .iter()
+            .filter_map(|db| {
+                let path = sync_dir.join(format!("{}.files", db.name()));
+
+                if path.exists() {
+                    Some((db.name().to_string(), path))
+                } else {
+                    log_warn!("No files database for {}, skipping it", db.name());
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Every table holding per-package rows, keyed by `(repo, name)`
+    const CACHE_PACKAGE_TABLES: &[&str] = &[
+        "package_desc",
+        "package_files",
+        "package_depends",
+        "package_groups",
+        "package_licenses",
+    ];
+
+    /// Full-text indexes whose rowids follow `package_desc` rowids, cleared before the rows they point to
+    const CACHE_ROWID_TABLES: &[&str] = &["package_fts", "package_name_trigram"];
+
+    /// Deletes packages of `repo` whose `name-version` was not seen in its archive, returns how many
+    fn prune_stale_packages(conn: &Connection, repo: &str, seen: &HashSet<String>) -> Result<usize> {
+        let stale: Vec<String> = {
+            let mut stmt = conn.prepare("SELECT name, name || '-' || version FROM package_desc WHERE repo = ?1")?;
+
+            stmt.query_map([repo], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+                .filter_map(|r| r.ok())
+                .filter(|(_, identifier)| !seen.contains(identifier))
+                .map(|(name, _)| name)
+                .collect()
+        };
+
+        for name in &stale {
+            for table in Self::CACHE_ROWID_TABLES {
+                conn.execute(
+                    &format!("DELETE FROM {table} WHERE rowid IN (SELECT rowid FROM package_desc WHERE repo = ?1 AND name = ?2)"),
+                    (repo, name),
+                )?;
+            }
+
+            for table in Self::CACHE_PACKAGE_TABLES {
+                conn.execute(
+                    &format!("DELETE FROM {table} WHERE repo = ?1 AND name = ?2"),
+                    (repo, name),
+                )?;
+            }
+        }
+
+        // files of packages whose description is gone, e.g. after an interrupted update
+        conn.execute(
+            "
+            DELETE FROM package_files
+            WHERE repo = ?1 AND name NOT IN (SELECT name FROM package_desc WHERE repo = ?1)
+            ",
+            [repo],
+        )?;
+
+        Ok(stale.len())
+    }
+
+    /// Deletes every repository not in `repos`, returns how many
+    fn prune_stale_repos(conn: &Connection, repos: &[&str]) -> Result<usize> {
+        let stale: Vec<String> = {
+            let mut stmt = conn.prepare("SELECT DISTINCT repo FROM package_desc")?;
+
+            stmt.query_map([], |row| row.get(0))?
+                .filter_map(|r| r.ok())
+                .filter(|repo: &String| !repos.contains(&repo.as_str()))
+                .collect()
+        };
+
+        for repo in &stale {
+            for table in Self::CACHE_ROWID_TABLES {
+                conn.execute(
+                    &format!("DELETE FROM {table} WHERE rowid IN (SELECT rowid FROM package_desc WHERE repo = ?1)"),
+                    [repo],
+                )?;
+            }
+
+            for table in Self::CACHE_PACKAGE_TABLES {
+                conn.execute(&format!("DELETE FROM {table} WHERE repo = ?1"), [repo])?;
+            }
+        }
+
+        Ok(stale.len())
+    }
+
+    /// Distinct trigrams of a word padded with `$`, so that its first and last letters weigh as much as the others
+    fn trigrams(word: &str) -> HashSet<String> {
+        let padded: Vec<char> = format!("${word}$").chars().collect();
+
+        padded
+            .windows(3)
+            .map(|w| w.iter().collect())
+            .collect()
+    }
+
+    /// Brings the vocabulary of every name and description word, together with its trigrams, in line
+    /// with the cached packages; words are shared between packages, so the whole vocabulary is
+    /// recomputed but only the words that appeared or disappeared are written
+    fn update_fuzzy_index(conn: &mut Connection) -> Result<()> {
+        let words: HashSet<String> = {
+            let mut stmt = conn.prepare("SELECT name, desc FROM package_desc")?;
+
+            stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)))?
+                .filter_map(|r| r.ok())
+                .flat_map(|(name, desc)| {
+                    let mut words = Self::tokenize(&name);
+                    words.extend(Self::tokenize(&desc.unwrap_or_default()));
+                    words
+                })
+                .collect()
+        };
+
+        let indexed: HashSet<String> = {
+            let mut stmt = conn.prepare("SELECT word FROM fuzzy_words")?;
+
+            stmt.query_map([], |row| row.get(0))?
+                .filter_map(|r| r.ok())
+                .collect()
+        };
+
+        let tx = conn.transaction()?;
+
+        {
+            let mut delete_word = tx.prepare_cached("DELETE FROM fuzzy_words WHERE word = ?1")?;
+            let mut delete_trigram = tx.prepare_cached("DELETE FROM fuzzy_trigrams WHERE trigram = ?1 AND word = ?2")?;
+
+            for word in indexed.difference(&words) {
+                delete_word.execute([word])?;
+
+                for trigram in Self::trigrams(word) {
+                    delete_trigram.execute((&trigram, word))?;
+                }
+            }
+
+            let mut insert_word = tx.prepare_cached("INSERT INTO fuzzy_words (word, len) VALUES (?1, ?2)")?;
+            let mut insert_trigram = tx.prepare_cached("INSERT INTO fuzzy_trigrams (trigram, word) VALUES (?1, ?2)")?;
 
-        for entry in archive.entries().map_err(|_| Error::ExtractArchive)? {
-            pb.inc(1);
-            total_pb.inc(1);
+            for word in words.difference(&indexed) {
+                insert_word.execute((word, word.chars().count() as i64))?;
 
-            let mut entry = entry.map_err(|_| Error::ExtractArchive)?;
-            if !entry.header().entry_type().is_file() {
-                continue;
+                for trigram in Self::trigrams(word) {
+                    insert_trigram.execute((&trigram, word))?;
+                }
             }
-
-            f(&mut entry)?;
         }
 
-        pb.set_style(
-            ProgressStyle::with_template("[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% {msg}")
-                .unwrap()
-                .progress_chars("=> ")
-        );
-        pb.finish_with_message(format!("caching {repo}: {action} done"));
+        tx.commit()?;
 
         Ok(())
     }
 
-    fn parse_entry_path(entry: &tar::Entry<GzDecoder<fs::File>>) -> Result<(String, String)> {
-        let path = entry.path().map_err(|_| Error::ExtractArchive)?;
-        let parts: Vec<_> = path.iter().map(|os| os.to_string_lossy()).collect();
-        if parts.len() < 2 {
-            return Err(Error::ExtractArchive);
+    /// Updates the cache in place, a missing or incomplete cache is built into a temporary file
+    /// which replaces it only once complete
+    pub fn update_cache(&self) -> Result<()> {
+        let cache_path = self.cache_path();
+        let _lock = Self::lock_cache(&cache_path)?;
+
+        match Self::cache_state(&cache_path)? {
+            CacheState::Ready | CacheState::Outdated => {
+                let mut conn = Self::open_cache_for_writing(&cache_path)?;
+                self.fill_cache(&mut conn)
+            }
+            CacheState::Missing | CacheState::Incomplete => {
+                let tmp_path = cache_path.with_extension("sqlite.tmp");
+
+                if tmp_path.exists() {
+                    fs::remove_file(&tmp_path)?;
+                }
+
+                {
+                    let mut conn = Connection::open(&tmp_path)?;
+
+                    // a failed build is thrown away as a whole, no need to journal it
+                    conn.pragma_update_and_check(None, "journal_mode", "OFF", |row| row.get::<_, String>(0))?;
+                    conn.pragma_update(None, "synchronous", "OFF")?;
+
+                    self.fill_cache(&mut conn)?;
+                }
+
+                // synchronous is OFF, so the contents must reach the disk before the rename makes them the cache
+                fs::File::open(&tmp_path)?.sync_all()?;
+
+                // leftovers of the old cache must not be applied to the new one
+                for suffix in ["-wal", "-shm"] {
+                    let mut path = cache_path.clone().into_os_string();
+                    path.push(suffix);
+
+                    if Path::new(&path).exists() {
+                        fs::remove_file(&path)?;
+                    }
+                }
+
+                fs::rename(&tmp_path, &cache_path)?;
+
+                if let Some(dir) = cache_path.parent() {
+                    fs::File::open(dir)?.sync_all()?;
+                }
+
+                Self::open_cache_for_writing(&cache_path)?;
+
+                Ok(())
+            }
         }
-        Ok((parts[0].to_string(), parts[1].to_string()))
     }
 
-    pub fn update_cache(&self) -> Result<()> {
-        let cache_path = Path::new(NAPM_CACHE_FILE);
-        let needs_init = !cache_path.exists();
-        let mut conn = Connection::open(cache_path)?;
+    fn fill_cache(&self, conn: &mut Connection) -> Result<()> {
+        Self::migrate_cache(conn)?;
 
-        if needs_init {
-            log_warn!("Creating the cache from scratch, this will take some time...");
-            Self::init_cache_schema(&conn)?;
-        }
+        let changes_before = conn.total_changes();
 
-        let handle = self.h();
-        let sync_dir = Path::new(handle.dbpath()).join("sync");
+        let archives = self.repo_archives();
 
-        let mut total_work = 0usize;
+        let mut total_size = 0;
 
-        for entry in fs::read_dir(&sync_dir)? {
-            let entry = entry?;
-            let path = entry.path();
+        for (_, path) in &archives {
+            total_size += fs::metadata(path)?.len();
+        }
 
-            let Some(fname) = path.file_name().and_then(|n| n.to_str()) else { continue; };
-            if !fname.ends_with(".files") { continue; }
+        // nothing is indexed yet (new cache or a migration), indexing everything at the end is much cheaper
+        let bulk_load = !conn
+            .prepare("SELECT 1 FROM package_desc WHERE files_done")?
+            .exists([])?;
 
-            total_work += 2 * Self::count_archive_files(&path)?;
+        if bulk_load {
+            Self::drop_cache_lookup_indexes(conn)?;
         }
 
         let mp = MultiProgress::new();
-        let total_pb = mp.add(ProgressBar::new(total_work as u64));
+        let total_pb = mp.add(ProgressBar::new(total_size));
 
         total_pb.set_style(
             ProgressStyle::with_template(
-                "[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% caching total {pos}/{len} ETA {eta}"
+                "[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% caching total {bytes}/{total_bytes} ETA {eta}"
             )
             .unwrap()
             .progress_chars("=> "),
         );
 
-        for entry in fs::read_dir(&sync_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-
-            let Some(fname) = path.file_name().and_then(|n| n.to_str()) else { continue; };
-            if !fname.ends_with(".files") { continue; }
+        let mut pruned = 0;
 
-            let repo = fname.trim_end_matches(".files");
+        for (repo, path) in &archives {
+            let repo = repo.as_str();
+            let tx = conn.transaction()?;
 
             let already_cached: HashSet<String> = {
-                let mut stmt = conn.prepare("SELECT name || '-' || version FROM package_desc WHERE repo = ?1 AND files_done")?;
+                let mut stmt = tx.prepare("SELECT name || '-' || version FROM package_desc WHERE repo = ?1 AND files_done")?;
 
                 stmt.query_map([&repo], |row| row.get(0))?
                     .filter_map(|r| r.ok())
                     .collect()
             };
 
-            let mut id_to_pkg: HashMap<String, String> = HashMap::new();
+            let mut seen: HashSet<String> = HashSet::new();
 
-            Self::process_archive(&mp, &total_pb, &path, repo, "descriptions", |entry| {
+            // `desc` and `files` of a package may come in any order, whichever comes first waits for the other
+            let mut pending_descs: HashMap<String, HashMap<String, Vec<String>>> = HashMap::new();
+            let mut pending_files: HashMap<String, String> = HashMap::new();
+
+            Self::process_archive(&mp, &total_pb, path, repo, |entry| {
                 let (identifier, file_name) = Self::parse_entry_path(entry)?;
-                if file_name != "desc" || already_cached.contains(&identifier) {
-                    return Ok(());
+
+                if file_name == "desc" {
+                    seen.insert(identifier.clone());
                 }
 
-                let mut contents = Vec::new();
-                entry.read_to_end(&mut contents)?;
-                let contents = String::from_utf8(contents)
-                    .map_err(|_| Error::ExtractArchive)?;
-
-                let mut name = None;
-                let mut version = None;
-                let mut desc = None;
-
-                let mut lines = contents.lines();
-                while let Some(tag) = lines.next() {
-                    match tag {
-                        "%NAME%" =>    name =    lines.next().map(str::to_string),
-                        "%VERSION%" => version = lines.next().map(str::to_string),
-                        "%DESC%" =>    desc =    lines.next().map(str::to_string),
-                        _ => {}
-                    }
+                if already_cached.contains(&identifier) {
+                    return Ok(());
                 }
 
-                let pkg_name = name.clone().unwrap();
-                id_to_pkg.insert(identifier.clone(), pkg_name.clone());
+                match file_name.as_str() {
+                    "desc" => {
+                        let desc = Self::parse_desc(&Self::read_entry(entry)?);
 
-                let pkg = Pkg {
-                    repo: repo.to_string(),
-                    name: pkg_name,
-                    version: version.unwrap(),
-                    desc: desc.unwrap_or_default(),
-                };
+                        match pending_files.remove(&identifier) {
+                            Some(files) => Self::cache_pkg(&tx, repo, &desc, Some(&files))?,
+                            None => { pending_descs.insert(identifier, desc); }
+                        }
+                    }
+                    "files" => {
+                        let files = Self::read_entry(entry)?;
 
-                conn.execute(
-                    "INSERT OR REPLACE INTO package_desc (name, version, desc, repo, files_done) VALUES (?1, ?2, ?3, ?4, false)",
-                    (&pkg.name, &pkg.version, &pkg.desc, &pkg.repo),
-                )?;
+                        match pending_descs.remove(&identifier) {
+                            Some(desc) => Self::cache_pkg(&tx, repo, &desc, Some(&files))?,
+                            None => { pending_files.insert(identifier, files); }
+                        }
+                    }
+                    _ => (),
+                }
 
                 Ok(())
             })?;
 
-            Self::process_archive(&mp, &total_pb, &path, repo, "files", |entry| {
-                let (identifier, file_name) = Self::parse_entry_path(entry)?;
-                if file_name != "files" || already_cached.contains(&identifier) {
-                    return Ok(());
-                }
+            for (identifier, desc) in &pending_descs {
+                log_warn!("Package {identifier} found in desc, but not in files");
+                Self::cache_pkg(&tx, repo, desc, None)?;
+            }
 
-                if let Some(package_name) = id_to_pkg.get(&identifier) {
-                    let tx = conn.transaction()?;
+            for identifier in pending_files.keys() {
+                log_warn!("Package {identifier} found in files, but not in desc");
+            }
 
-                    tx.execute(
-                        "DELETE FROM package_files WHERE repo = ?1 AND name = ?2",
-                        (&repo, &package_name),
-                    )?;
+            pruned += Self::prune_stale_packages(&tx, repo, &seen)?;
 
-                    let mut contents = Vec::new();
-                    entry.read_to_end(&mut contents)?;
-                    let contents = String::from_utf8(contents)
-                        .map_err(|_| Error::ExtractArchive)?;
+            tx.commit()?;
+        }
 
-                    for line in contents.lines().skip(1) {
-                        tx.execute(
-                            "INSERT INTO package_files (repo, name, path) VALUES (?1, ?2, ?3)",
-                            (&repo, &package_name, &line),
-                        )?;
-                    }
+        // a repo whose files database is missing keeps its old rows, only repos gone from pacman.conf are pruned
+        let repos: Vec<&str> = self.h().syncdbs().iter().map(|db| db.name()).collect();
+        let pruned_repos = Self::prune_stale_repos(conn, &repos)?;
 
-                    tx.execute(
-                        "UPDATE package_desc SET files_done = true WHERE repo = ?1 AND name = ?2",
-                        (&repo, &package_name),
-                    )?;
+        Self::create_cache_lookup_indexes(conn)?;
 
-                    tx.commit()?;
-                } else {
-                    log_warn!("Package {identifier} found in files, but not in desc");
-                }
+        let fuzzy_index_empty = !conn.prepare("SELECT 1 FROM fuzzy_words")?.exists([])?;
 
-                Ok(())
-            })?;
+        if conn.total_changes() != changes_before || fuzzy_index_empty {
+            Self::update_fuzzy_index(conn)?;
         }
 
+        conn.execute(
+            "INSERT OR REPLACE INTO cache_meta (key, value) VALUES ('last_update', strftime('%s', 'now'))",
+            (),
+        )?;
+
         total_pb.set_style(
             ProgressStyle::with_template(
                 "[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% caching done"
@@ -291,50 +1188,130 @@ impl Napm {
         );
         total_pb.finish();
 
+        if pruned > 0 {
+            log_info!("Pruned {pruned} package(s) no longer in the sync databases");
+        }
+
+        if pruned_repos > 0 {
+            log_info!("Pruned {pruned_repos} repository(ies) no longer in the configuration");
+        }
+
         Ok(())
     }
 
-    pub fn info(&self, pkg_name: &str) -> Result<Pkg> {
-        require_cache()?;
-
-        let cache_path = Path::new(NAPM_CACHE_FILE);
-        
-        let conn = Connection::open(cache_path)?;
+    /// Full metadata of the highest priority searchable sync package, answered from the cache only
+    pub fn cached_pkg_info(&self, pkg_name: &str) -> Result<PkgInfo> {
+        let conn = self.open_cache()?;
 
         let mut stmt = conn.prepare(&format!(
             "
-            SELECT name, version, repo, desc
+            SELECT name, version, desc, repo, arch, url, packager, build_date, download_size, installed_size
             FROM package_desc
-            WHERE name = ?1 AND repo = (
-                SELECT repo
-                FROM package_desc
-                WHERE name = ?1
-                ORDER BY {}
-                LIMIT 1
-            )
+            WHERE name = ?1 AND {}
+            ORDER BY {}
+            LIMIT 1
             ",
+            self.repo_usage_filter("repo", Usage::SEARCH),
             self.repo_priority()
         ))?;
 
         use rusqlite::Error as E;
-        match stmt.query_one([pkg_name], |row| Ok(Pkg {
+        let mut info = match stmt.query_one([pkg_name], |row| Ok(PkgInfo {
             name: row.get(0)?,
             version: row.get(1)?,
-            desc: row.get(2)?,
+            desc: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
             repo: row.get(3)?,
+            arch: row.get(4)?,
+            url: row.get(5)?,
+            packager: row.get(6)?,
+            build_date: row.get::<_, Option<i64>>(7)?.unwrap_or(0),
+            download_size: row.get(8)?,
+            installed_size: row.get::<_, Option<i64>>(9)?.unwrap_or(0),
+            licenses: Vec::new(),
+            groups: Vec::new(),
+            provides: Vec::new(),
+            depends: Vec::new(),
+            optdepends: Vec::new(),
+            required_by: Vec::new(),
+            optional_for: Vec::new(),
+            conflicts: Vec::new(),
+            replaces: Vec::new(),
+            install_date: None,
+            install_reason: None,
+            validation: Vec::new(),
         })) {
-            Ok(pkg) => Ok(pkg),
-            Err(E::QueryReturnedNoRows) => Err(Error::PackageNotFound(pkg_name.to_string())),
-            Err(err) => Err(Error::CacheDatabaseError(err)),
-        }
+            Ok(info) => info,
+            Err(E::QueryReturnedNoRows) => return Err(Error::PackageNotFound(pkg_name.to_string())),
+            Err(err) => return Err(Error::CacheDatabaseError(err)),
+        };
+
+        let key = (info.repo.as_str(), info.name.as_str());
+
+        let list = |sql: &str| -> Result<Vec<String>> {
+            Ok(conn.prepare(sql)?
+                .query_map(key, |row| row.get(0))?
+                .filter_map(|r| r.ok())
+                .collect())
+        };
+
+        let deps = |kind: &str| list(&format!(
+            "SELECT dep FROM package_depends WHERE repo = ?1 AND name = ?2 AND kind = '{kind}' ORDER BY rowid"
+        ));
+
+        info.licenses = list("SELECT license FROM package_licenses WHERE repo = ?1 AND name = ?2 ORDER BY rowid")?;
+        info.groups = list("SELECT grp FROM package_groups WHERE repo = ?1 AND name = ?2 ORDER BY rowid")?;
+        info.provides = deps("provides")?;
+        info.depends = deps("depends")?;
+        info.conflicts = deps("conflicts")?;
+        info.replaces = deps("replaces")?;
+
+        let installed = self.h().localdb().pkgs();
+
+        info.optdepends = conn
+            .prepare("SELECT dep, desc FROM package_depends WHERE repo = ?1 AND name = ?2 AND kind = 'optdepends' ORDER BY rowid")?
+            .query_map(key, |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)))?
+            .filter_map(|r| r.ok())
+            .map(|(dep, desc)| OptDep {
+                installed: installed.find_satisfier(dep.as_str()).is_some(),
+                dep,
+                desc,
+            })
+            .collect();
+
+        info.required_by = self.cached_reverse_deps(&conn, &info.repo, &info.name, "depends")?;
+        info.optional_for = self.cached_reverse_deps(&conn, &info.repo, &info.name, "optdepends")?;
+
+        Ok(info)
     }
 
-    pub fn files(&self, pkg_name: &str, with_dirs: bool) -> Result<Vec<String>> {
-        require_cache()?;
+    /// Searchable packages whose `kind` relation (e.g. depends) is satisfied by the name or a provide of a package
+    fn cached_reverse_deps(&self, conn: &Connection, repo: &str, pkg_name: &str, kind: &str) -> Result<Vec<String>> {
+        let mut stmt = conn.prepare(&format!(
+            "
+            SELECT DISTINCT d.name
+            FROM package_depends AS d
+            WHERE d.kind = ?3 AND {} AND (
+                d.target = ?2
+                OR d.target IN (
+                    SELECT target
+                    FROM package_depends
+                    WHERE repo = ?1 AND name = ?2 AND kind = 'provides'
+                )
+            )
+            ORDER BY d.name
+            ",
+            self.repo_usage_filter("d.repo", Usage::SEARCH),
+        ))?;
 
-        let cache_path = Path::new(NAPM_CACHE_FILE);
-        
-        let conn = Connection::open(cache_path)?;
+        Ok(
+            stmt.query_map((repo, pkg_name, kind), |row| row.get(0))?
+                .filter_map(|r| r.ok())
+                .collect()
+        )
+    }
+
+    pub fn files(&self, pkg_name: &str, with_dirs: bool) -> Result<Vec<String>> {
+        let conn = self.open_cache()?;
 
         if !Self::pkg_exists(&conn, pkg_name)? {
             return Err(Error::PackageNotFound(pkg_name.to_string()));
@@ -363,12 +1340,47 @@ impl Napm {
         )
     }
 
-    pub fn find_packages_by_file(&self, path: &str, exact: bool) -> Result<Vec<(Pkg, String)>> {
-        require_cache()?;
-        
-        let cache_path = Path::new(NAPM_CACHE_FILE);
-        
-        let conn = Connection::open(cache_path)?;
+    /// Escapes `LIKE` wildcards, to be used with `ESCAPE '\'`
+    fn escape_like(s: &str) -> String {
+        s.replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    }
+
+    pub fn find_packages_by_file(&self, path: &str, opts: &FindOptions) -> Result<Vec<(Pkg, String)>> {
+        if opts.mode == FindMode::Regex {
+            Regex::new(path).map_err(|err| Error::InvalidRegex(err.to_string()))?;
+        }
+
+        let conn = self.open_cache()?;
+
+        let (condition, pattern) = match opts.mode {
+            FindMode::Exact => ("'/' || f.path = ?", path.to_string()),
+            FindMode::Suffix => ("'/' || f.path LIKE ? ESCAPE '\\'", format!("%{}", Self::escape_like(path))),
+            FindMode::Glob => ("'/' || f.path GLOB ?", path.to_string()),
+            FindMode::Regex => ("'/' || f.path REGEXP ?", path.to_string()),
+        };
+
+        let mut conditions = vec![condition.to_string()];
+        let mut params: Vec<Value> = vec![pattern.into()];
+
+        if !opts.repos.is_empty() {
+            conditions.push(format!("d.repo IN ({})", vec!["?"; opts.repos.len()].join(", ")));
+            params.extend(opts.repos.iter().cloned().map(Value::from));
+        }
+
+        if opts.installed {
+            conditions.push("d.name IN (SELECT key FROM json_each(?))".to_string());
+            params.push(self.installed_json().into());
+        }
+
+        // the highest priority repo is picked among the requested ones, its placeholders come last
+        let priority_filter = if opts.repos.is_empty() {
+            String::new()
+        } else {
+            params.extend(opts.repos.iter().cloned().map(Value::from));
+            format!("AND d2.repo IN ({})", vec!["?"; opts.repos.len()].join(", "))
+        };
 
         let mut stmt = conn.prepare(&format!(
             "
@@ -385,26 +1397,19 @@ impl Napm {
             AND d.repo = (
                 SELECT d2.repo
                 FROM package_desc AS d2
-                WHERE d2.name = d.name
+                WHERE d2.name = d.name {}
                 ORDER BY {}
                 LIMIT 1
             )
             ORDER BY d.name, f.path;
             ",
-            if exact {
-                "'/' || f.path = ?1"
-            } else {
-                "'/' || f.path LIKE ?1"
-            },
+            conditions.join(" AND "),
+            priority_filter,
             self.repo_priority_with_column_name("d2.repo"),
         ))?;
 
         Ok(
-            stmt.query_map([&if exact { 
-                path.to_string()
-            } else {
-                format!("%{path}")
-            }], |row| Ok((
+            stmt.query_map(rusqlite::params_from_iter(params), |row| Ok((
                 Pkg {
                     name: row.get(0)?,
                     version: row.get(1)?,
@@ -425,30 +1430,150 @@ impl Napm {
             .collect()
     }
 
+    /// Installed packages as a JSON `{name: version}` object, for `json_each` in queries
+    fn installed_json(&self) -> String {
+        let installed: serde_json::Map<String, serde_json::Value> = self.h()
+            .localdb()
+            .pkgs()
+            .iter()
+            .map(|p| (p.name().to_string(), p.version().to_string().into()))
+            .collect();
+
+        serde_json::Value::Object(installed).to_string()
+    }
+
+    /// Packages matching any of the words by prefix in the full-text index, anywhere in their name
+    /// through the trigram index (or by exact name), with their BM25 score and every filter of `opts` applied
     fn select_candidates(
         &self,
         conn: &Connection,
         query_words: &[String],
-    ) -> Result<Vec<Pkg>> {
+        opts: &SearchOptions,
+    ) -> Result<Vec<(f64, Pkg)>> {
+
+        let placeholders = |n: usize| vec!["?"; n].join(", ");
+
+        let mut conditions = vec![self.repo_usage_filter("d.repo", Usage::SEARCH)];
+        let mut params: Vec<Value> = Vec::new();
+
+        let (fts, from, score) = if opts.exact_name {
+            conditions.push(format!("LOWER(d.name) IN ({})", placeholders(query_words.len())));
+            params.extend(query_words.iter().cloned().map(Value::from));
+
+            (String::new(), "package_desc AS d", "0.0")
+        } else if query_words.is_empty() {
+            (String::new(), "package_desc AS d", "0.0")
+        } else {
+            // words only hold alphanumerics (see `tokenize`), quoting them is enough
+            let match_expr = |words: &[&String], suffix: &str| words
+                .iter()
+                .map(|q| format!("\"{q}\"{suffix}"))
+                .collect::<Vec<_>>()
+                .join(" OR ");
+
+            // the MATCH placeholders come first in the statement
+            params.push(match_expr(&query_words.iter().collect::<Vec<_>>(), "*").into());
+
+            // fts only matches word prefixes, so names are also matched anywhere through their trigrams
+            // ("ssl" finds openssl), which needs at least three letters; shorter words stick to prefixes
+            let substrings = query_words.iter().filter(|q| q.chars().count() >= 3).collect::<Vec<_>>();
+
+            let trigram_hits = if substrings.is_empty() {
+                ""
+            } else {
+                params.push(match_expr(&substrings, "").into());
 
-        let mut where_clauses = Vec::new();
-        let mut params = Vec::new();
+                "
+                    UNION ALL
+                    SELECT rowid, -bm25(package_name_trigram) AS rank
+                    FROM package_name_trigram
+                    WHERE package_name_trigram MATCH ?"
+            };
 
-        for q in query_words {
-            where_clauses.push("(LOWER(name) LIKE ? OR LOWER(desc) LIKE ?)");
-            let like = format!("%{}%", q);
-            params.push(like.clone());
-            params.push(like);
+            // bm25 is lower for better matches, weights follow the column order: repo, name, desc, provides, groups
+            (
+                format!(
+                    "
+                hits AS MATERIALIZED (
+                    SELECT rowid, -bm25(package_fts, 0.0, 10.0, 1.0, 5.0, 2.0) AS rank
+                    FROM package_fts
+                    WHERE package_fts MATCH ?{trigram_hits}
+                ),
+                fts AS (
+                    SELECT rowid, MAX(rank) AS rank
+                    FROM hits
+                    GROUP BY rowid
+                ),"
+                ),
+                "fts AS f JOIN package_desc AS d ON d.rowid = f.rowid",
+                "f.rank",
+            )
+        };
+
+        if !opts.repos.is_empty() {
+            conditions.push(format!("d.repo IN ({})", placeholders(opts.repos.len())));
+            params.extend(opts.repos.iter().cloned().map(Value::from));
         }
 
+        if opts.installed.is_some() || opts.upgradable {
+            let installed = self.installed_json();
+
+            match opts.installed {
+                Some(true) => conditions.push("d.name IN (SELECT key FROM json_each(?))".to_string()),
+                Some(false) => conditions.push("d.name NOT IN (SELECT key FROM json_each(?))".to_string()),
+                None => (),
+            }
+
+            if opts.installed.is_some() {
+                params.push(installed.clone().into());
+            }
+
+            if opts.upgradable {
+                conditions.push(
+                    "EXISTS (SELECT 1 FROM json_each(?) AS l WHERE l.key = d.name AND vercmp(d.version, l.value) > 0)".to_string()
+                );
+                params.push(installed.into());
+            }
+        }
+
+        if !opts.groups.is_empty() {
+            conditions.push(format!(
+                "EXISTS (SELECT 1 FROM package_groups AS g WHERE g.repo = d.repo AND g.name = d.name AND g.grp IN ({}))",
+                placeholders(opts.groups.len())
+            ));
+            params.extend(opts.groups.iter().cloned().map(Value::from));
+        }
+
+        if !opts.licenses.is_empty() {
+            conditions.push(format!(
+                "EXISTS (SELECT 1 FROM package_licenses AS l WHERE l.repo = d.repo AND l.name = d.name AND l.license IN ({}))",
+                placeholders(opts.licenses.len())
+            ));
+            params.extend(opts.licenses.iter().cloned().map(Value::from));
+        }
+
+        if let Some(arch) = &opts.arch {
+            conditions.push("d.arch = ?".to_string());
+            params.push(arch.clone().into());
+        }
+
+        let order_by = match opts.sort {
+            // refined by `score_packages`
+            SearchSort::Relevance => "score DESC",
+            SearchSort::Name => "name ASC",
+            SearchSort::Size => "installed_size DESC",
+            SearchSort::Date => "build_date DESC",
+        };
+
         let sql = format!(
             "
-            WITH matched AS (
-                SELECT *
-                FROM package_desc
+            WITH {fts}
+            matched AS (
+                SELECT d.name, d.version, d.desc, d.repo, d.installed_size, d.build_date, {score} AS score
+                FROM {from}
                 WHERE {}
             )
-            SELECT name, version, desc, repo
+            SELECT name, version, desc, repo, score
             FROM matched AS d
             WHERE repo = (
                 SELECT repo
@@ -457,8 +1582,9 @@ impl Napm {
                 ORDER BY {}
                 LIMIT 1
             )
+            ORDER BY {order_by}
             ",
-            where_clauses.join(" OR "),
+            conditions.join(" AND "),
             self.repo_priority_with_column_name("d2.repo")
         );
 
@@ -467,12 +1593,12 @@ impl Napm {
         let rows = stmt.query_map(
             rusqlite::params_from_iter(params),
             |row| {
-                Ok(Pkg {
+                Ok((row.get(4)?, Pkg {
                     name: row.get(0)?,
                     version: row.get(1)?,
                     desc: row.get(2)?,
                     repo: row.get(3)?,
-                })
+                }))
             },
         )?;
 
@@ -514,148 +1640,370 @@ impl Napm {
         (d <= max_dist).then_some(d)
     }
 
+    /// Maps every query word to the known words within typo distance of it, including itself at distance 0
     fn expand_query_words(
         conn: &Connection,
         query_words: &[String],
-    ) -> Result<Vec<String>> {
-
-        let mut stmt = conn.prepare(
-            "SELECT DISTINCT LOWER(name) FROM package_desc"
-        )?;
-
-        let dict: Vec<String> = stmt
-            .query_map([], |row| row.get(0))?
-            .filter_map(rusqlite::Result::ok)
-            .collect();
+    ) -> Result<HashMap<String, HashMap<String, usize>>> {
 
         const MAX_DISTANCE: usize = 2;
         const MAX_LEN_DIFF: usize = 2;
 
-        let mut expanded = std::collections::HashSet::new();
+        let mut expanded = HashMap::new();
 
         for q in query_words {
-            expanded.insert(q.clone());
-
-            for w in &dict {
-                if w.len().abs_diff(q.len()) > MAX_LEN_DIFF {
-                    continue;
-                }
-
-                if Self::levenshtein_cutoff(w, q, MAX_DISTANCE).is_some() {
-                    expanded.insert(w.clone());
-                }
-            }
-        }
-
-        Ok(expanded.into_iter().collect())
-    }
-
-    fn compute_df(
-        candidates: &[Pkg],
-        query_words: &[String],
-    ) -> HashMap<String, usize> {
+            let trigrams: Vec<String> = Self::trigrams(q).into_iter().collect();
+            let len = q.chars().count();
+
+            // every edit breaks at most 3 trigrams, short words have to share at least one
+            let min_shared = trigrams.len().saturating_sub(3 * MAX_DISTANCE).max(1);
+
+            let sql = format!(
+                "
+                SELECT t.word
+                FROM fuzzy_trigrams AS t
+                JOIN fuzzy_words AS w ON w.word = t.word
+                WHERE t.trigram IN ({}) AND w.len BETWEEN ? AND ?
+                GROUP BY t.word
+                HAVING COUNT(*) >= ?
+                ",
+                vec!["?"; trigrams.len()].join(", ")
+            );
 
-        let mut df = HashMap::new();
+            let mut params: Vec<rusqlite::types::Value> = trigrams
+                .into_iter()
+                .map(rusqlite::types::Value::from)
+                .collect();
+            params.push((len.saturating_sub(MAX_LEN_DIFF) as i64).into());
+            params.push(((len + MAX_LEN_DIFF) as i64).into());
+            params.push((min_shared as i64).into());
 
-        for pkg in candidates {
-            let text = format!(
-                "{} {}",
-                pkg.name.to_lowercase(),
-                pkg.desc.to_lowercase()
-            );
+            let mut stmt = conn.prepare(&sql)?;
 
-            let tokens = Self::tokenize(&text);
+            let mut similar: HashMap<String, usize> = stmt
+                .query_map(rusqlite::params_from_iter(params), |row| row.get::<_, String>(0))?
+                .filter_map(rusqlite::Result::ok)
+                .filter_map(|w| Self::levenshtein_cutoff(&w, q, MAX_DISTANCE).map(|d| (w, d)))
+                .collect();
 
-            for q in query_words {
-                if tokens.iter().any(|t| t == q) {
-                    *df.entry(q.clone()).or_insert(0) += 1;
-                }
-            }
+            similar.insert(q.clone(), 0);
+            expanded.insert(q.clone(), similar);
         }
 
-        df
+        Ok(expanded)
     }
 
     fn fuzzy_weight(d: usize) -> f64 {
         (3 - d) as f64
     }
 
+    /// Adds name and typo bonuses on top of the full-text score
     fn score_packages(
-        candidates: Vec<Pkg>,
-        query_words: &[String],
-        df: &HashMap<String, usize>,
+        candidates: Vec<(f64, Pkg)>,
+        expanded: &HashMap<String, HashMap<String, usize>>,
     ) -> Vec<(f64, Pkg)> {
 
-        const MAX_DISTANCE: usize = 2;
-        const MAX_LEN_DIFF: usize = 2;
-
-        let total_docs = candidates.len().max(1) as f64;
         let mut scored = Vec::new();
 
-        for pkg in candidates {
-            let mut score = 0.0;
+        for (fts_score, pkg) in candidates {
+            let mut score = fts_score;
 
             let name_lc = pkg.name.to_lowercase();
-            let desc_lc = pkg.desc.to_lowercase();
-            let desc_tokens = Self::tokenize(&desc_lc);
-
-            for q in query_words {
-                let df_q = *df.get(q).unwrap_or(&1) as f64;
-                let idf = (total_docs / df_q).ln();
-
-                if name_lc.contains(q) {
-                    score += 5.0 * idf;
-                }
-
-                if desc_tokens.iter().any(|t| *t == *q) {
-                    score += 1.5 * idf;
+            let mut tokens = Self::tokenize(&pkg.name);
+            tokens.extend(Self::tokenize(&pkg.desc));
+
+            for (q, similar) in expanded {
+                if name_lc == *q {
+                    score += 10.0;
+                } else if name_lc.contains(q.as_str()) {
+                    score += 5.0;
                 }
 
-                for token in std::iter::once(name_lc.as_str())
-                    .chain(desc_tokens.iter().map(String::as_str))
-                {
-                    if token.len().abs_diff(q.len()) > MAX_LEN_DIFF {
-                        continue;
-                    }
-
-                    if let Some(d) = Self::levenshtein_cutoff(token, q, MAX_DISTANCE) {
-                        score += Self::fuzzy_weight(d) * idf;
+                for token in &tokens {
+                    if let Some(&d) = similar.get(token) {
+                        score += Self::fuzzy_weight(d);
                     }
                 }
             }
 
-            if score > 0.0 {
-                scored.push((score, pkg));
-            }
+            scored.push((score, pkg));
         }
 
         scored
     }
 
-    pub fn search(&self, search_terms: Vec<String>) -> Result<Vec<Pkg>> {
-        require_cache()?;
+    pub fn search(&self, search_terms: Vec<String>, opts: &SearchOptions) -> Result<Vec<Pkg>> {
+        let conn = self.open_cache()?;
+
+        if opts.exact_name {
+            let names: Vec<String> = search_terms.iter().map(|t| t.to_lowercase()).collect();
 
-        let conn = Connection::open(NAPM_CACHE_FILE)?;
+            if names.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let candidates = self.select_candidates(&conn, &names, opts)?;
+
+            return Ok(candidates.into_iter().map(|(_, pkg)| pkg).collect());
+        }
 
         let query = search_terms.join(" ");
         let query_words = Self::tokenize(&query);
 
-        if query_words.is_empty() {
+        if query_words.is_empty() && !opts.has_filters() {
             return Ok(Vec::new());
         }
 
         let expanded = Self::expand_query_words(&conn, &query_words)?;
-        let candidates = self.select_candidates(&conn, &expanded)?;
+
+        let match_words: Vec<String> = expanded
+            .values()
+            .flat_map(|similar| similar.keys().cloned())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        let candidates = self.select_candidates(&conn, &match_words, opts)?;
 
         if candidates.is_empty() {
             return Ok(Vec::new());
         }
 
-        let df = Self::compute_df(&candidates, &query_words);
-        let mut scored = Self::score_packages(candidates, &query_words, &df);
+        let mut scored = Self::score_packages(candidates, &expanded);
 
-        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        if opts.sort == SearchSort::Relevance {
+            scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        }
 
         Ok(scored.into_iter().map(|(_, pkg)| pkg).collect())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The same two-package files database, once per compression `pacman -Fy` may hand us
+    const FIXTURES: &[&str] = &["plain", "gzip", "zstd", "xz", "bzip2"];
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/files-db").join(format!("{name}.files"))
+    }
+
+    #[test]
+    fn decompress_detects_every_format() {
+        for name in FIXTURES {
+            let reader = ProgressReader {
+                inner: fs::File::open(fixture(name)).unwrap(),
+                bars: [ProgressBar::hidden(), ProgressBar::hidden()],
+            };
+
+            let mut tar = Vec::new();
+            Napm::decompress(reader).unwrap().read_to_end(&mut tar).unwrap();
+
+            assert_eq!(tar, fs::read(fixture("plain")).unwrap(), "{name}");
+        }
+    }
+
+    #[test]
+    fn process_archive_parses_every_format() {
+        for name in FIXTURES {
+            let mp = MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden());
+            let total_pb = mp.add(ProgressBar::hidden());
+
+            let mut descs = HashMap::new();
+            let mut files = HashMap::new();
+
+            Napm::process_archive(&mp, &total_pb, &fixture(name), "test", |entry| {
+                let (identifier, file_name) = Napm::parse_entry_path(entry)?;
+                let contents = Napm::read_entry(entry)?;
+
+                match file_name.as_str() {
+                    "desc" => { descs.insert(identifier, Napm::parse_desc(&contents)); }
+                    "files" => { files.insert(identifier, contents); }
+                    other => panic!("unexpected entry {other}"),
+                }
+
+                Ok(())
+            }).unwrap();
+
+            assert_eq!(descs.len(), 2, "{name}");
+            assert_eq!(files.len(), 2, "{name}");
+
+            let foo = &descs["foo-1.0-1"];
+            assert_eq!(foo["NAME"], ["foo"], "{name}");
+            assert_eq!(foo["VERSION"], ["1.0-1"], "{name}");
+            assert_eq!(foo["DESC"], ["A test package"], "{name}");
+            assert_eq!(foo["DEPENDS"], ["glibc", "bar>=2.0"], "{name}");
+            assert_eq!(descs["bar-2.0-3"]["NAME"], ["bar"], "{name}");
+
+            assert!(files["foo-1.0-1"].lines().any(|l| l == "usr/bin/foo"), "{name}");
+            assert!(files["bar-2.0-3"].lines().any(|l| l == "usr/share/bar/data"), "{name}");
+        }
+    }
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        conn.prepare("SELECT name FROM pragma_table_info(?1)").unwrap()
+            .query_map([table], |row| row.get(0)).unwrap()
+            .collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn migrate_cache_upgrades_a_v1_cache() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        Napm::init_cache_schema(&conn).unwrap();
+        assert_eq!(Napm::cache_schema_version(&conn).unwrap(), 1);
+
+        Napm::migrate_cache(&mut conn).unwrap();
+
+        let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, CACHE_SCHEMA_VERSION);
+
+        let desc = columns(&conn, "package_desc");
+        for column in ["name", "version", "desc", "repo", "files_done", "base", "arch", "url", "packager", "build_date", "download_size", "installed_size"] {
+            assert!(desc.contains(&column.to_string()), "package_desc.{column}");
+        }
+
+        assert_eq!(columns(&conn, "package_depends"), ["repo", "name", "kind", "target", "dep", "desc"]);
+        assert_eq!(columns(&conn, "package_groups"), ["repo", "name", "grp"]);
+        assert_eq!(columns(&conn, "package_licenses"), ["repo", "name", "license"]);
+        assert_eq!(columns(&conn, "cache_meta"), ["key", "value"]);
+        assert_eq!(columns(&conn, "package_fts"), ["repo", "name", "desc", "provides", "groups"]);
+        assert_eq!(columns(&conn, "fuzzy_words"), ["word", "len"]);
+        assert_eq!(columns(&conn, "fuzzy_trigrams"), ["trigram", "word"]);
+        assert_eq!(columns(&conn, "package_name_trigram"), ["name"]);
+
+        // already up to date, nothing to do
+        Napm::migrate_cache(&mut conn).unwrap();
+    }
+
+    #[test]
+    fn migrate_cache_refuses_a_newer_cache() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        Napm::init_cache_schema(&conn).unwrap();
+        conn.pragma_update(None, "user_version", CACHE_SCHEMA_VERSION + 1).unwrap();
+
+        match Napm::migrate_cache(&mut conn) {
+            Err(Error::CacheTooNew(found, supported)) => {
+                assert_eq!(found, CACHE_SCHEMA_VERSION + 1);
+                assert_eq!(supported, CACHE_SCHEMA_VERSION);
+            }
+            other => panic!("expected CacheTooNew, got {other:?}"),
+        }
+    }
+
+    fn cache_with(pkgs: &[(&str, &str, &str)]) -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        Napm::migrate_cache(&mut conn).unwrap();
+
+        for (repo, name, version) in pkgs {
+            let desc = HashMap::from([
+                ("NAME".to_string(), vec![name.to_string()]),
+                ("VERSION".to_string(), vec![version.to_string()]),
+                ("DESC".to_string(), vec![format!("The {name} package")]),
+                ("DEPENDS".to_string(), vec!["glibc".to_string()]),
+                ("GROUPS".to_string(), vec!["base".to_string()]),
+                ("LICENSE".to_string(), vec!["MIT".to_string()]),
+            ]);
+
+            Napm::cache_pkg(&conn, repo, &desc, Some(&format!("%FILES%\nusr/bin/{name}\n"))).unwrap();
+        }
+
+        conn
+    }
+
+    /// Asserts that every per-package table holds exactly `expected` as `repo/name`,
+    /// and that the full-text indexes point to those packages only
+    fn assert_cached(conn: &Connection, expected: &[&str]) {
+        for table in Napm::CACHE_PACKAGE_TABLES {
+            let found: Vec<String> = conn.prepare(&format!("SELECT DISTINCT repo || '/' || name FROM {table} ORDER BY 1")).unwrap()
+                .query_map([], |row| row.get(0)).unwrap()
+                .collect::<rusqlite::Result<_>>().unwrap();
+
+            assert_eq!(found, expected, "{table}");
+        }
+
+        for table in Napm::CACHE_ROWID_TABLES {
+            let found: Vec<String> = conn.prepare(&format!(
+                "SELECT d.repo || '/' || d.name FROM {table} AS t LEFT JOIN package_desc AS d ON d.rowid = t.rowid ORDER BY 1"
+            )).unwrap()
+                .query_map([], |row| row.get(0)).unwrap()
+                .collect::<rusqlite::Result<_>>().unwrap();
+
+            assert_eq!(found, expected, "{table}");
+        }
+    }
+
+    #[test]
+    fn prune_stale_packages_drops_packages_gone_from_the_repo() {
+        let conn = cache_with(&[("core", "bar", "2.0-1"), ("core", "foo", "1.0-1"), ("extra", "baz", "1.0-1")]);
+
+        let seen = HashSet::from(["foo-1.0-1".to_string()]);
+        assert_eq!(Napm::prune_stale_packages(&conn, "core", &seen).unwrap(), 1);
+
+        assert_cached(&conn, &["core/foo", "extra/baz"]);
+    }
+
+    #[test]
+    fn prune_stale_repos_drops_repos_gone_from_pacman_conf() {
+        let conn = cache_with(&[("core", "foo", "1.0-1"), ("testing", "bar", "2.0-1")]);
+
+        assert_eq!(Napm::prune_stale_repos(&conn, &["core"]).unwrap(), 1);
+
+        assert_cached(&conn, &["core/foo"]);
+    }
+
+    #[test]
+    fn prune_stale_repos_keeps_repos_without_a_files_db() {
+        let conn = cache_with(&[("core", "foo", "1.0-1"), ("extra", "bar", "2.0-1")]);
+
+        // only core had a files database to read, extra is still a configured sync db
+        let seen = HashSet::from(["foo-1.0-1".to_string()]);
+        assert_eq!(Napm::prune_stale_packages(&conn, "core", &seen).unwrap(), 0);
+        assert_eq!(Napm::prune_stale_repos(&conn, &["core", "extra"]).unwrap(), 0);
+
+        assert_cached(&conn, &["core/foo", "extra/bar"]);
+    }
+
+    fn fuzzy_words(conn: &Connection) -> HashSet<String> {
+        conn.prepare("SELECT word FROM fuzzy_words").unwrap()
+            .query_map([], |row| row.get(0)).unwrap()
+            .collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn expand_query_words_finds_typos_within_distance_2() {
+        let mut conn = cache_with(&[("core", "firefox", "1.0-1"), ("extra", "thunderbird", "1.0-1"), ("extra", "vim", "9.0-1")]);
+        Napm::update_fuzzy_index(&mut conn).unwrap();
+
+        let query = ["firefx", "thundrbrd", "fierfox", "vim", "zsh"].map(String::from);
+        let expanded = Napm::expand_query_words(&conn, &query).unwrap();
+
+        assert_eq!(expanded["firefx"].get("firefox"), Some(&1));
+        assert_eq!(expanded["thundrbrd"].get("thunderbird"), Some(&2));
+        assert_eq!(expanded["fierfox"].get("firefox"), Some(&2));
+        assert_eq!(expanded["vim"].get("vim"), Some(&0));
+
+        // unrelated words are left alone
+        assert!(!expanded["firefx"].contains_key("thunderbird"));
+        assert!(!expanded["vim"].contains_key("firefox"));
+        assert_eq!(expanded["zsh"], HashMap::from([("zsh".to_string(), 0)]));
+    }
+
+    #[test]
+    fn update_fuzzy_index_follows_package_changes() {
+        let mut conn = cache_with(&[("core", "foo", "1.0-1"), ("core", "bar", "1.0-1")]);
+        Napm::update_fuzzy_index(&mut conn).unwrap();
+
+        assert_eq!(fuzzy_words(&conn), HashSet::from(["foo", "bar", "the", "package"].map(String::from)));
+
+        let seen = HashSet::from(["foo-1.0-1".to_string()]);
+        Napm::prune_stale_packages(&conn, "core", &seen).unwrap();
+        Napm::update_fuzzy_index(&mut conn).unwrap();
+
+        assert_eq!(fuzzy_words(&conn), HashSet::from(["foo", "the", "package"].map(String::from)));
+
+        let bar_trigrams: i64 = conn.query_row("SELECT COUNT(*) FROM fuzzy_trigrams WHERE word = 'bar'", [], |row| row.get(0)).unwrap();
+        assert_eq!(bar_trigrams, 0);
+    }
+}
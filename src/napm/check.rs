@@ -0,0 +1,412 @@
+use flate2::read::GzDecoder;
+use indicatif::ProgressBar;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::Path,
+};
+
+use crate::error::{Error, Result};
+use crate::napm::Napm;
+use crate::pkg::Pkg;
+use crate::log_warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProblemKind {
+    Missing,
+    Type,
+    Checksum,
+    Size,
+    Permissions,
+    Owner,
+    Mtime,
+    Symlink,
+    /// The package's mtree cannot be read, its files are not checked
+    Mtree,
+    /// Not counted as a problem, backup files are meant to be edited
+    BackupModified,
+}
+
+impl ProblemKind {
+    /// Same as the serialized name
+    pub fn key(&self) -> &'static str {
+        match self {
+            ProblemKind::Missing => "missing",
+            ProblemKind::Type => "type",
+            ProblemKind::Checksum => "checksum",
+            ProblemKind::Size => "size",
+            ProblemKind::Permissions => "permissions",
+            ProblemKind::Owner => "owner",
+            ProblemKind::Mtime => "mtime",
+            ProblemKind::Symlink => "symlink",
+            ProblemKind::Mtree => "mtree",
+            ProblemKind::BackupModified => "backup-modified",
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            ProblemKind::Missing => "missing",
+            ProblemKind::Type => "file type changed",
+            ProblemKind::Checksum => "checksum changed",
+            ProblemKind::Size => "size changed",
+            ProblemKind::Permissions => "permissions changed",
+            ProblemKind::Owner => "owner changed",
+            ProblemKind::Mtime => "modification time changed",
+            ProblemKind::Symlink => "symlink target changed",
+            ProblemKind::Mtree => "mtree unreadable",
+            ProblemKind::BackupModified => "backup file modified",
+        }
+    }
+
+    pub fn is_problem(&self) -> bool {
+        *self != ProblemKind::BackupModified
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    pub path: String,
+    pub expected: Option<String>,
+    pub found: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PkgCheck {
+    #[serde(flatten)]
+    pub package: Pkg,
+    /// Number of files, directories and symlinks checked
+    pub checked: usize,
+    pub problems: Vec<Problem>,
+}
+
+/// One entry of a package's `.MTREE`, fields are `None` when it does not record them
+#[derive(Debug, Clone, Default)]
+struct MtreeEntry {
+    path: String,
+    kind: Option<String>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    mode: Option<u32>,
+    size: Option<u64>,
+    time: Option<i64>,
+    sha256: Option<String>,
+    link: Option<String>,
+}
+
+impl MtreeEntry {
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "type" => self.kind = Some(value.to_string()),
+            "uid" => self.uid = value.parse().ok(),
+            "gid" => self.gid = value.parse().ok(),
+            "mode" => self.mode = u32::from_str_radix(value, 8).ok(),
+            "size" => self.size = value.parse().ok(),
+            "time" => self.time = value.split('.').next().and_then(|s| s.parse().ok()),
+            "sha256digest" => self.sha256 = Some(value.to_string()),
+            "link" => self.link = Some(Napm::mtree_unescape(value)),
+            _ => (),
+        }
+    }
+
+    fn unset(&mut self, key: &str) {
+        match key {
+            "type" => self.kind = None,
+            "uid" => self.uid = None,
+            "gid" => self.gid = None,
+            "mode" => self.mode = None,
+            "size" => self.size = None,
+            "time" => self.time = None,
+            "sha256digest" => self.sha256 = None,
+            "link" => self.link = None,
+            "all" => *self = Self::default(),
+            _ => (),
+        }
+    }
+}
+
+impl Napm {
+    /// Decodes the `\NNN` octal escapes mtree uses for special characters in paths
+    fn mtree_unescape(s: &str) -> String {
+        let bytes = s.as_bytes();
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+
+        while i < bytes.len() {
+            let octal = bytes.get(i + 1..i + 4)
+                .filter(|d| d.iter().all(|b| (b'0'..=b'7').contains(b)))
+                .and_then(|d| u8::from_str_radix(std::str::from_utf8(d).ok()?, 8).ok());
+
+            match (bytes[i], octal) {
+                (b'\\', Some(byte)) => {
+                    out.push(byte);
+                    i += 4;
+                }
+                (byte, _) => {
+                    out.push(byte);
+                    i += 1;
+                }
+            }
+        }
+
+        String::from_utf8_lossy(&out).to_string()
+    }
+
+    /// Parses an mtree file, `/set` and `/unset` apply to every following entry
+    fn parse_mtree(contents: &str) -> Vec<MtreeEntry> {
+        let mut defaults = MtreeEntry::default();
+        let mut entries = Vec::new();
+
+        for line in contents.replace("\\\n", " ").lines() {
+            let mut words = line.split_whitespace();
+
+            let Some(first) = words.next() else { continue; };
+            if first.starts_with('#') { continue; }
+
+            match first {
+                "/set" => {
+                    for word in words {
+                        if let Some((key, value)) = word.split_once('=') {
+                            defaults.set(key, value);
+                        }
+                    }
+                }
+                "/unset" => {
+                    for key in words {
+                        defaults.unset(key);
+                    }
+                }
+                path => {
+                    let mut entry = defaults.clone();
+                    entry.path = Self::mtree_unescape(path);
+
+                    for word in words {
+                        if let Some((key, value)) = word.split_once('=') {
+                            entry.set(key, value);
+                        }
+                    }
+
+                    entries.push(entry);
+                }
+            }
+        }
+
+        entries
+    }
+
+    /// Compares one mtree entry to the file on disk, `backup_md5` is set for backup files
+    fn check_mtree_entry(root: &Path, entry: &MtreeEntry, backup_md5: Option<&str>) -> Result<Vec<Problem>> {
+        let rel = entry.path.trim_start_matches("./");
+        let full = root.join(rel);
+        let path = format!("/{rel}");
+
+        let problem = |kind, expected: Option<String>, found: Option<String>| Problem {
+            kind,
+            path: path.clone(),
+            expected,
+            found,
+        };
+
+        let Ok(meta) = fs::symlink_metadata(&full) else {
+            return Ok(vec![problem(ProblemKind::Missing, None, None)]);
+        };
+
+        let found_kind = if meta.is_symlink() {
+            "link"
+        } else if meta.is_dir() {
+            "dir"
+        } else {
+            "file"
+        };
+
+        if let Some(kind) = &entry.kind && kind != found_kind {
+            return Ok(vec![problem(ProblemKind::Type, Some(kind.clone()), Some(found_kind.to_string()))]);
+        }
+
+        let mut problems = Vec::new();
+
+        if found_kind == "link" {
+            let target = fs::read_link(&full)?.to_string_lossy().to_string();
+
+            if let Some(link) = &entry.link && *link != target {
+                problems.push(problem(ProblemKind::Symlink, Some(link.clone()), Some(target)));
+            }
+        } else if let Some(mode) = entry.mode && mode != meta.mode() & 0o7777 {
+            problems.push(problem(ProblemKind::Permissions, Some(format!("{mode:o}")), Some(format!("{:o}", meta.mode() & 0o7777))));
+        }
+
+        if let (Some(uid), Some(gid)) = (entry.uid, entry.gid) && (uid, gid) != (meta.uid(), meta.gid()) {
+            problems.push(problem(ProblemKind::Owner, Some(format!("{uid}:{gid}")), Some(format!("{}:{}", meta.uid(), meta.gid()))));
+        }
+
+        if found_kind != "file" {
+            return Ok(problems);
+        }
+
+        // unreadable files (e.g. /etc/shadow when not root) are skipped rather than failing the whole check
+        if let Some(backup_md5) = backup_md5 {
+            match alpm::compute_md5sum(full.as_os_str().as_bytes()) {
+                Ok(md5) if md5 != backup_md5 => problems.push(problem(ProblemKind::BackupModified, None, None)),
+                Ok(_) => (),
+                Err(err) => log_warn!("Cannot read {path}: {err}"),
+            }
+
+            return Ok(problems);
+        }
+
+        if let Some(time) = entry.time && time != meta.mtime() {
+            problems.push(problem(ProblemKind::Mtime, Some(time.to_string()), Some(meta.mtime().to_string())));
+        }
+
+        if let Some(size) = entry.size && size != meta.size() {
+            problems.push(problem(ProblemKind::Size, Some(size.to_string()), Some(meta.size().to_string())));
+        } else if let Some(sha256) = &entry.sha256 {
+            match alpm::compute_sha256sum(full.as_os_str().as_bytes()) {
+                Ok(hash) if hash != *sha256 => problems.push(problem(ProblemKind::Checksum, None, None)),
+                Ok(_) => (),
+                Err(err) => log_warn!("Cannot read {path}: {err}"),
+            }
+        }
+
+        Ok(problems)
+    }
+
+    /// Verifies the installed files of `pkg_names` (every installed package when empty) against their mtree
+    pub fn check_pkgs(&self, pkg_names: &[&str]) -> Result<Vec<PkgCheck>> {
+        let localdb = self.h().localdb();
+
+        let pkgs = if pkg_names.is_empty() {
+            localdb.pkgs().iter().collect::<Vec<_>>()
+        } else {
+            pkg_names
+                .iter()
+                .map(|name| localdb.pkg(*name).map_err(|_| Error::PackageNotInLocalDb(name.to_string())))
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        let root = Path::new(&self.config.root_dir);
+        let local_dir = Path::new(self.h().dbpath()).join("local");
+
+        let pb = ProgressBar::new(pkgs.len() as u64);
+        pb.set_style(Napm::progress_bar_total_style().clone());
+        pb.set_message("checking packages");
+
+        let mut results = Vec::new();
+
+        for pkg in pkgs {
+            pb.inc(1);
+
+            let backups: HashMap<&str, &str> = pkg.backup()
+                .iter()
+                .map(|b| (b.name(), b.hash()))
+                .collect();
+
+            let mtree_path = local_dir
+                .join(format!("{}-{}", pkg.name(), pkg.version()))
+                .join("mtree");
+
+            let mut checked = 0;
+            let mut problems = Vec::new();
+
+            if let Ok(file) = fs::File::open(&mtree_path) {
+                let mut contents = String::new();
+
+                // a corrupt mtree is a problem of this package, the others are still checked
+                if let Err(err) = GzDecoder::new(file).read_to_string(&mut contents) {
+                    problems.push(Problem {
+                        kind: ProblemKind::Mtree,
+                        path: mtree_path.display().to_string(),
+                        expected: None,
+                        found: Some(err.to_string()),
+                    });
+
+                    contents.clear();
+                }
+
+                for entry in Self::parse_mtree(&contents) {
+                    // package metadata like ./.PKGINFO is not installed
+                    if entry.path.starts_with("./.") {
+                        continue;
+                    }
+
+                    let backup_md5 = backups.get(entry.path.trim_start_matches("./")).copied();
+
+                    checked += 1;
+                    problems.extend(Self::check_mtree_entry(root, &entry, backup_md5)?);
+                }
+            } else {
+                pb.suspend(|| log_warn!("No mtree for {}, only checking that its files exist", pkg.name()));
+
+                for file in pkg.files().files() {
+                    let name = String::from_utf8_lossy(file.name()).to_string();
+
+                    checked += 1;
+
+                    if fs::symlink_metadata(root.join(&name)).is_err() {
+                        problems.push(Problem {
+                            kind: ProblemKind::Missing,
+                            path: format!("/{name}"),
+                            expected: None,
+                            found: None,
+                        });
+                    }
+                }
+            }
+
+            results.push(PkgCheck {
+                package: Pkg::from(pkg),
+                checked,
+                problems,
+            });
+        }
+
+        pb.finish_and_clear();
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mtree_applies_set_and_unset() {
+        let entries = Napm::parse_mtree("\
+#mtree
+/set type=file uid=0 gid=0 mode=644
+./usr/bin/foo time=1700000000.0 mode=755 size=3 sha256digest=abc
+/unset sha256digest
+./usr/share/foo\\040bar size=0
+./usr/lib/libfoo.so type=link link=libfoo.so.1
+/unset all
+./etc/foo.conf
+");
+
+        assert_eq!(entries.len(), 4);
+
+        let foo = &entries[0];
+        assert_eq!(foo.path, "./usr/bin/foo");
+        assert_eq!(foo.kind.as_deref(), Some("file"));
+        assert_eq!((foo.uid, foo.gid, foo.mode), (Some(0), Some(0), Some(0o755)));
+        assert_eq!((foo.size, foo.time), (Some(3), Some(1700000000)));
+        assert_eq!(foo.sha256.as_deref(), Some("abc"));
+
+        let bar = &entries[1];
+        assert_eq!(bar.path, "./usr/share/foo bar");
+        assert_eq!(bar.mode, Some(0o644));
+        assert_eq!(bar.sha256, None);
+
+        let lib = &entries[2];
+        assert_eq!(lib.kind.as_deref(), Some("link"));
+        assert_eq!(lib.link.as_deref(), Some("libfoo.so.1"));
+
+        let conf = &entries[3];
+        assert_eq!((conf.kind.as_deref(), conf.uid, conf.mode, conf.sha256.as_deref()), (None, None, None, None));
+    }
+}
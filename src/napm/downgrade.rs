@@ -0,0 +1,169 @@
+use alpm::vercmp;
+use std::{
+    cmp::Ordering,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::error::{Error, Result};
+use crate::napm::Napm;
+use crate::pkg::{Pkg, PkgFile};
+use crate::util::choose;
+use crate::{log_info, log_warn};
+
+/// Package archive found in a cache dir or the archive dir
+#[derive(Debug, Clone)]
+pub struct CachedPkg {
+    pub name: String,
+    pub version: String,
+    pub path: PathBuf,
+}
+
+impl CachedPkg {
+    /// Parses `{name}-{pkgver}-{pkgrel}-{arch}.pkg.tar.*`, signatures and partial downloads are skipped
+    fn from_path(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?;
+
+        if file_name.ends_with(".sig") || file_name.ends_with(".part") {
+            return None;
+        }
+
+        let (stem, _) = file_name.split_once(".pkg.tar")?;
+        let mut parts = stem.rsplitn(4, '-');
+
+        let _arch = parts.next()?;
+        let pkgrel = parts.next()?;
+        let pkgver = parts.next()?;
+        let name = parts.next()?;
+
+        Some(Self {
+            name: name.to_string(),
+            version: format!("{pkgver}-{pkgrel}"),
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Napm {
+    /// Every version of `name` in the configured cache dirs and `archive_dir`, newest first
+    pub fn cached_versions(&self, name: &str, archive_dir: Option<&Path>) -> Vec<CachedPkg> {
+        let cache_dirs = self.h().cachedirs().iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        let mut found: Vec<CachedPkg> = Vec::new();
+
+        for dir in cache_dirs.iter().map(PathBuf::as_path).chain(archive_dir) {
+            let Ok(entries) = fs::read_dir(dir) else {
+                log_warn!("Cannot read {}, skipping it", dir.display());
+                continue;
+            };
+
+            for entry in entries.flatten() {
+                let Some(cached) = CachedPkg::from_path(&entry.path()) else { continue; };
+
+                // the same version can be in several dirs, the first one wins
+                if cached.name == name && !found.iter().any(|c| c.version == cached.version) {
+                    found.push(cached);
+                }
+            }
+        }
+
+        found.sort_by(|a, b| vercmp(b.version.as_str(), a.version.as_str()));
+
+        found
+    }
+
+    /// Lets the user pick a cached version of an installed package and installs it
+    pub fn downgrade(&mut self, name: &str, archive_dir: Option<&Path>) -> Result<()> {
+        let installed = self.local_pkg(name)?;
+        let cached = self.cached_versions(name, archive_dir);
+
+        if cached.is_empty() {
+            return Err(Error::NoCachedVersions(name.to_string()));
+        }
+
+        let options = cached
+            .iter()
+            .map(|c| {
+                let label = match vercmp(c.version.as_str(), installed.version.as_str()) {
+                    Ordering::Equal => " (installed)",
+                    _ => "",
+                };
+
+                format!("{}{label} {}", Pkg::format_name(&c.name, Some(&c.version)), c.path.display())
+            })
+            .collect::<Vec<_>>();
+
+        // default to the newest version older than the installed one
+        let default = cached
+            .iter()
+            .position(|c| vercmp(c.version.as_str(), installed.version.as_str()) == Ordering::Less)
+            .unwrap_or(0);
+
+        let choice = choose(&format!("Which version of {} do you want to install?", installed.formatted_name(true)), &options, default as i32)?;
+        let chosen = &cached[choice as usize];
+
+        log_warn!(
+            "Partial upgrades are unsupported, {} may break packages that depend on a newer version, and the next upgrade will bring it back unless it is in IgnorePkg",
+            Pkg::format_name(&chosen.name, Some(&chosen.version)),
+        );
+
+        self.install_pkgs(&[], &[PkgFile { path: chosen.path.to_string_lossy().to_string(), remote: false }], None)
+    }
+
+    /// Reinstalls the installed version of packages, from the sync repos when they still have it or else from the cache dirs
+    pub fn reinstall(&mut self, names: &[&str]) -> Result<()> {
+        let mut pkgs = Vec::new();
+        let mut files = Vec::new();
+
+        for name in names {
+            let installed = self.local_pkg(name)?;
+
+            match self.pkg(name) {
+                Ok(pkg) if pkg.version == installed.version => pkgs.push(pkg),
+                _ => {
+                    let cached = self
+                        .cached_versions(name, None)
+                        .into_iter()
+                        .find(|c| c.version == installed.version)
+                        .ok_or(Error::NoCachedVersions(format!("{}-{}", installed.name, installed.version)))?;
+
+                    log_info!("{} is not in the sync repos anymore, using {}", installed.formatted_name(true), cached.path.display());
+
+                    files.push(PkgFile { path: cached.path.to_string_lossy().to_string(), remote: false });
+                }
+            }
+        }
+
+        self.install_pkgs(&pkgs, &files, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(file_name: &str) -> Option<(String, String)> {
+        CachedPkg::from_path(&Path::new("/var/cache/pacman/pkg").join(file_name)).map(|c| (c.name, c.version))
+    }
+
+    #[test]
+    fn from_path_parses_package_archives() {
+        assert_eq!(parse("vim-9.1.0-1-x86_64.pkg.tar.zst"), Some(("vim".into(), "9.1.0-1".into())));
+        assert_eq!(parse("python-foo-bar-1.0-1-any.pkg.tar.zst"), Some(("python-foo-bar".into(), "1.0-1".into())));
+        assert_eq!(parse("lib32-gcc-libs-14.2.1+r134-1-x86_64.pkg.tar.xz"), Some(("lib32-gcc-libs".into(), "14.2.1+r134-1".into())));
+        assert_eq!(parse("java-runtime-common-1:2.0-3-any.pkg.tar.zst"), Some(("java-runtime-common".into(), "1:2.0-3".into())));
+        assert_eq!(parse("foo-1.0-1.1-x86_64.pkg.tar.gz"), Some(("foo".into(), "1.0-1.1".into())));
+
+        let cached = CachedPkg::from_path(Path::new("/var/cache/pacman/pkg/vim-9.1.0-1-x86_64.pkg.tar.zst")).unwrap();
+        assert_eq!(cached.path, Path::new("/var/cache/pacman/pkg/vim-9.1.0-1-x86_64.pkg.tar.zst"));
+    }
+
+    #[test]
+    fn from_path_skips_other_files() {
+        assert_eq!(parse("vim-9.1.0-1-x86_64.pkg.tar.zst.sig"), None);
+        assert_eq!(parse("vim-9.1.0-1-x86_64.pkg.tar.zst.part"), None);
+        assert_eq!(parse("download-XXXXXX"), None);
+        assert_eq!(parse("notes.txt"), None);
+        assert_eq!(parse("vim.pkg.tar.zst"), None);
+    }
+}
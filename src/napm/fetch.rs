@@ -0,0 +1,522 @@
+use alpm::FetchResult;
+use indicatif::{MultiProgress, ProgressBar};
+use percent_encoding::percent_decode_str;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+    process::Command,
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::error::{Error, Result};
+use crate::napm::Napm;
+use crate::log_warn;
+
+pub const DEFAULT_DOWNLOAD_TIMEOUT: u64 = 30;
+
+type Bars = Arc<Mutex<(MultiProgress, HashMap<String, ProgressBar>)>>;
+
+/// Fails a read when no data arrived for `timeout`, ureq only bounds the body as a whole so a
+/// mirror that stalls after the headers would otherwise hang before libalpm can try the next one
+struct IdleTimeoutReader {
+    rx: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    timeout: Duration,
+}
+
+impl IdleTimeoutReader {
+    /// Reads `inner` on its own thread, which ends with the connection once the receiver is gone
+    fn new(mut inner: impl Read + Send + 'static, timeout: Duration) -> Self {
+        let (tx, rx) = mpsc::sync_channel(4);
+
+        thread::spawn(move || {
+            let mut buf = [0u8; 64 * 1024];
+
+            loop {
+                let res = inner.read(&mut buf).map(|n| buf[..n].to_vec());
+                let done = !matches!(&res, Ok(chunk) if !chunk.is_empty());
+
+                if tx.send(res).is_err() || done {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+            timeout,
+        }
+    }
+}
+
+impl Read for IdleTimeoutReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            self.chunk = match self.rx.recv_timeout(self.timeout) {
+                Ok(res) => res?,
+                Err(mpsc::RecvTimeoutError::Timeout) => return Err(io::ErrorKind::TimedOut.into()),
+                Err(mpsc::RecvTimeoutError::Disconnected) => Vec::new(),
+            };
+            self.pos = 0;
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+/// State of the alpm fetch callback, replaces libalpm's built-in curl downloader.
+/// libalpm calls it once per server until one succeeds, so it only fetches the url it is given
+pub struct Fetcher {
+    xfer_command: Option<String>,
+    timeout: Option<Duration>,
+    bars: Bars,
+}
+
+impl Fetcher {
+    pub fn new(xfer_command: Option<String>, timeout: Option<Duration>, bars: Bars) -> Self {
+        Self {
+            xfer_command,
+            timeout,
+            bars,
+        }
+    }
+
+    pub fn fetch(&self, url: &str, dest_dir: &Path, force: bool) -> Result<FetchResult> {
+        let res = match &self.xfer_command {
+            Some(xfer_command) => Self::fetch_with_xfer_command(xfer_command, url, dest_dir),
+            None => self.fetch_native(url, dest_dir, force),
+        };
+
+        if let Err(err) = &res {
+            log_warn!("Failed to download {url}: {err}");
+        }
+
+        res
+    }
+
+    fn file_name(url: &str) -> Result<&str> {
+        url.rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty())
+            .ok_or(Error::Download(url.to_string()))
+    }
+
+    /// Runs `XferCommand`, substituting `%u` with the url and `%o` with the partial output file
+    fn fetch_with_xfer_command(xfer_command: &str, url: &str, dest_dir: &Path) -> Result<FetchResult> {
+        let file_name = Self::file_name(url)?;
+        let dest = dest_dir.join(file_name);
+        let part = dest_dir.join(format!("{file_name}.part"));
+        let use_part = xfer_command.contains("%o");
+
+        let args = xfer_command
+            .split_whitespace()
+            .map(|arg| arg
+                .replace("%o", &part.to_string_lossy())
+                .replace("%u", url)
+            )
+            .collect::<Vec<_>>();
+
+        let Some((program, args)) = args.split_first() else {
+            return Err(Error::XferCommand(xfer_command.to_string()));
+        };
+
+        let status = Command::new(program)
+            .args(args)
+            .current_dir(dest_dir)
+            .status()?;
+
+        if !status.success() {
+            return Err(Error::XferCommand(xfer_command.to_string()));
+        }
+
+        if use_part {
+            fs::rename(&part, &dest)?;
+        }
+
+        Ok(FetchResult::Ok)
+    }
+
+    /// Downloads `url` into `dest_dir`, resuming an existing `.part` file unless `force` is set
+    fn fetch_native(&self, url: &str, dest_dir: &Path, force: bool) -> Result<FetchResult> {
+        let file_name = Self::file_name(url)?;
+        let dest = dest_dir.join(file_name);
+        let part = dest_dir.join(format!("{file_name}.part"));
+
+        if force && part.exists() {
+            fs::remove_file(&part)?;
+        }
+
+        let mtime = if force {
+            None
+        } else {
+            fs::metadata(&dest).and_then(|m| m.modified()).ok()
+        };
+
+        let resume_from = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+
+        let (mut reader, total, modified, append): (Box<dyn Read>, Option<u64>, Option<SystemTime>, bool) =
+            if let Some(path) = url.strip_prefix("file://") {
+                let path = percent_decode_str(path).decode_utf8().map_err(|_| Error::Download(url.to_string()))?;
+                let path = path.as_ref();
+
+                let src_meta = fs::metadata(path).map_err(|_| Error::Download(url.to_string()))?;
+                let src_mtime = src_meta.modified().ok();
+
+                if let (Some(dest_mtime), Some(src_mtime)) = (mtime, src_mtime) && dest_mtime >= src_mtime {
+                    return Ok(FetchResult::FileExists);
+                }
+
+                let mut file = fs::File::open(path)?;
+                let append = resume_from > 0 && resume_from <= src_meta.len();
+                if append {
+                    std::io::Seek::seek(&mut file, std::io::SeekFrom::Start(resume_from))?;
+                }
+
+                (Box::new(file), Some(src_meta.len()), src_mtime, append)
+            } else {
+                let agent: ureq::Agent = ureq::Agent::config_builder()
+                    .http_status_as_error(false)
+                    .timeout_connect(self.timeout)
+                    .timeout_recv_response(self.timeout)
+                    .build()
+                    .into();
+
+                let mut req = agent.get(url);
+
+                if let Some(mtime) = mtime {
+                    req = req.header("If-Modified-Since", httpdate::fmt_http_date(mtime));
+                }
+
+                if resume_from > 0 {
+                    req = req.header("Range", format!("bytes={resume_from}-"));
+                }
+
+                let res = req.call().map_err(|_| Error::Download(url.to_string()))?;
+
+                let append = match res.status().as_u16() {
+                    304 => return Ok(FetchResult::FileExists),
+                    206 => true,
+                    200 => false,
+                    416 => {
+                        // the partial file does not match the remote one, start over next time
+                        let _ = fs::remove_file(&part);
+                        return Err(Error::Download(url.to_string()));
+                    }
+                    _ => return Err(Error::Download(url.to_string())),
+                };
+
+                let modified = res.headers()
+                    .get("last-modified")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| httpdate::parse_http_date(v).ok());
+
+                let total = res.body()
+                    .content_length()
+                    .map(|len| if append { len + resume_from } else { len });
+
+                let body = res.into_body().into_reader();
+                let reader: Box<dyn Read> = match self.timeout {
+                    Some(timeout) => Box::new(IdleTimeoutReader::new(body, timeout)),
+                    None => Box::new(body),
+                };
+
+                (reader, total, modified, append)
+            };
+
+        let mut out = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(&part)?;
+
+        let pb = self.progress_bar(file_name);
+        pb.set_length(total.unwrap_or(0));
+        pb.set_position(if append { resume_from } else { 0 });
+
+        let mut buf = [0u8; 64 * 1024];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(_) => {
+                    self.finish_progress_bar(file_name, false);
+                    return Err(Error::Download(url.to_string()));
+                }
+            };
+
+            out.write_all(&buf[..n])?;
+            pb.inc(n as u64);
+        }
+
+        out.flush()?;
+
+        if let Some(modified) = modified {
+            out.set_modified(modified)?;
+        }
+
+        drop(out);
+        fs::rename(&part, &dest)?;
+
+        self.finish_progress_bar(file_name, true);
+
+        Ok(FetchResult::Ok)
+    }
+
+    fn progress_bar(&self, file_name: &str) -> ProgressBar {
+        let mut bars_guard = self.bars.lock().unwrap();
+        let (mp, bar_map) = &mut *bars_guard;
+
+        bar_map
+            .entry(file_name.to_string())
+            .or_insert_with(|| {
+                let pb = mp.add(ProgressBar::new(100));
+                pb.set_style(Napm::progress_bar_style(false).clone());
+                pb.set_message(file_name.to_string());
+                pb
+            })
+            .clone()
+    }
+
+    fn finish_progress_bar(&self, file_name: &str, success: bool) {
+        let mut bars_guard = self.bars.lock().unwrap();
+        let (_, bar_map) = &mut *bars_guard;
+
+        if let Some(pb) = bar_map.remove(file_name) {
+            if success {
+                pb.finish_with_message(format!("{file_name} done"));
+            } else {
+                pb.set_style(Napm::progress_bar_style(true).clone());
+                pb.finish_with_message(format!("{file_name} failed"));
+            }
+        }
+    }
+}
+
+pub fn fetch_callback(
+    url: &str,
+    dest_dir: &str,
+    force: bool,
+    fetcher: &mut Fetcher,
+) -> FetchResult {
+    match fetcher.fetch(url, Path::new(dest_dir), force) {
+        Ok(res) => res,
+        Err(_) => FetchResult::Err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+
+    fn fetcher(xfer_command: Option<&str>, timeout: Option<Duration>) -> Fetcher {
+        Fetcher::new(
+            xfer_command.map(str::to_string),
+            timeout,
+            Arc::new(Mutex::new((MultiProgress::new(), HashMap::new()))),
+        )
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("napm-fetch-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Answers one connection per canned response on loopback, returns the base url and the requests received
+    fn serve(responses: Vec<Vec<u8>>, stall: Option<Duration>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+
+                requests.push(String::from_utf8_lossy(&request).to_lowercase());
+                stream.write_all(&response).unwrap();
+
+                if let Some(stall) = stall {
+                    thread::sleep(stall);
+                }
+            }
+
+            requests
+        });
+
+        (url, handle)
+    }
+
+    fn response(status: &str, body: &str) -> Vec<u8> {
+        format!("HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len()).into_bytes()
+    }
+
+    #[test]
+    fn file_url_full_download() {
+        let dir = temp_dir("file-full");
+        let src = dir.join("src");
+        let dest = dir.join("dest");
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(src.join("core.files"), "contents").unwrap();
+
+        let url = format!("file://{}", src.join("core.files").display());
+        let res = fetcher(None, None).fetch(&url, &dest, false).unwrap();
+
+        assert_eq!(res, FetchResult::Ok);
+        assert_eq!(fs::read_to_string(dest.join("core.files")).unwrap(), "contents");
+        assert!(!dest.join("core.files.part").exists());
+
+        // the source is not newer than what was downloaded
+        let res = fetcher(None, None).fetch(&url, &dest, false).unwrap();
+        assert_eq!(res, FetchResult::FileExists);
+    }
+
+    #[test]
+    fn file_url_resumes_part() {
+        let dir = temp_dir("file-resume");
+        fs::write(dir.join("src.files"), "hello world").unwrap();
+        let dest = dir.join("dest");
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(dest.join("src.files.part"), "hello ").unwrap();
+
+        let url = format!("file://{}", dir.join("src.files").display());
+        let res = fetcher(None, None).fetch(&url, &dest, false).unwrap();
+
+        assert_eq!(res, FetchResult::Ok);
+        assert_eq!(fs::read_to_string(dest.join("src.files")).unwrap(), "hello world");
+    }
+
+    #[test]
+    fn file_url_is_percent_decoded() {
+        let dir = temp_dir("file-decode");
+        let src = dir.join("my repo");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("core.files"), "contents").unwrap();
+
+        let url = format!("file://{}/my%20repo/core.files", dir.display());
+        let res = fetcher(None, None).fetch(&url, &dir, false).unwrap();
+
+        assert_eq!(res, FetchResult::Ok);
+        assert_eq!(fs::read_to_string(dir.join("core.files")).unwrap(), "contents");
+    }
+
+    #[test]
+    fn http_full_download() {
+        let dir = temp_dir("http-full");
+        let (url, server) = serve(vec![response("200 OK", "contents")], None);
+
+        let res = fetcher(None, None).fetch(&format!("{url}core.db"), &dir, false).unwrap();
+
+        assert_eq!(res, FetchResult::Ok);
+        assert_eq!(fs::read_to_string(dir.join("core.db")).unwrap(), "contents");
+        assert!(!dir.join("core.db.part").exists());
+        assert!(server.join().unwrap()[0].starts_with("get /core.db "));
+    }
+
+    #[test]
+    fn http_resumes_part_on_206() {
+        let dir = temp_dir("http-206");
+        fs::write(dir.join("core.db.part"), "hello ").unwrap();
+        let (url, server) = serve(vec![response("206 Partial Content", "world")], None);
+
+        let res = fetcher(None, None).fetch(&format!("{url}core.db"), &dir, false).unwrap();
+
+        assert_eq!(res, FetchResult::Ok);
+        assert_eq!(fs::read_to_string(dir.join("core.db")).unwrap(), "hello world");
+        assert!(server.join().unwrap()[0].contains("range: bytes=6-"));
+    }
+
+    #[test]
+    fn http_304_is_file_exists() {
+        let dir = temp_dir("http-304");
+        fs::write(dir.join("core.db"), "old").unwrap();
+        let (url, server) = serve(vec![response("304 Not Modified", "")], None);
+
+        let res = fetcher(None, None).fetch(&format!("{url}core.db"), &dir, false).unwrap();
+
+        assert_eq!(res, FetchResult::FileExists);
+        assert_eq!(fs::read_to_string(dir.join("core.db")).unwrap(), "old");
+        assert!(server.join().unwrap()[0].contains("if-modified-since: "));
+    }
+
+    #[test]
+    fn http_416_removes_part() {
+        let dir = temp_dir("http-416");
+        fs::write(dir.join("core.db.part"), "garbage").unwrap();
+        let (url, server) = serve(vec![response("416 Range Not Satisfiable", "")], None);
+
+        let res = fetcher(None, None).fetch(&format!("{url}core.db"), &dir, false);
+
+        assert!(res.is_err());
+        assert!(!dir.join("core.db.part").exists());
+        assert!(!dir.join("core.db").exists());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn stalled_body_times_out() {
+        let dir = temp_dir("stall");
+        let stalled = b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\npartial".to_vec();
+        let (url, _) = serve(vec![stalled], Some(Duration::from_secs(6)));
+
+        let start = std::time::Instant::now();
+        let res = fetcher(None, Some(Duration::from_secs(1))).fetch(&format!("{url}core.db"), &dir, false);
+
+        // gave up well before the server closed the connection, so libalpm can try its next server
+        assert!(res.is_err());
+        assert!(start.elapsed() < Duration::from_secs(4));
+        assert!(!dir.join("core.db").exists());
+    }
+
+    #[test]
+    fn xfer_command_substitutes_url_and_output() {
+        let dir = temp_dir("xfer");
+        let src = dir.join("src");
+        let dest = dir.join("dest");
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(src.join("core.db"), "contents").unwrap();
+
+        let url = src.join("core.db").display().to_string();
+        let res = fetcher(Some("cp %u %o"), None).fetch(&url, &dest, false).unwrap();
+
+        assert_eq!(res, FetchResult::Ok);
+        assert_eq!(fs::read_to_string(dest.join("core.db")).unwrap(), "contents");
+        assert!(!dest.join("core.db.part").exists());
+    }
+
+    #[test]
+    fn failed_xfer_command_is_an_error() {
+        let dir = temp_dir("xfer-fail");
+
+        let res = fetcher(Some("false %u %o"), None).fetch("http://a/core/core.db", &dir, false);
+
+        assert!(matches!(res, Err(Error::XferCommand(_))));
+    }
+}
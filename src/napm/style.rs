@@ -5,6 +5,7 @@ use crate::napm::Napm;
 
 static PROGRESS_BAR_STYLE: OnceLock<ProgressStyle> = OnceLock::new();
 static PROGRESS_BAR_STYLE_FAILED: OnceLock<ProgressStyle> = OnceLock::new();
+static PROGRESS_BAR_STYLE_TOTAL: OnceLock<ProgressStyle> = OnceLock::new();
 
 impl Napm {
     pub fn progress_bar_style(failed: bool) -> &'static ProgressStyle {
@@ -24,4 +25,12 @@ impl Napm {
             })
         }
     }
-}
\ No newline at end of file
+
+    pub fn progress_bar_total_style() -> &'static ProgressStyle {
+        PROGRESS_BAR_STYLE_TOTAL.get_or_init(|| {
+            ProgressStyle::with_template("[{elapsed:>3}] [{bar:40.cyan/blue}] {pos}/{len} {msg}")
+                .unwrap()
+                .progress_chars("=> ")
+        })
+    }
+}
@@ -1,8 +1,9 @@
 use alpm::{
-    Alpm, SigLevel,
+    Alpm, Db, SigLevel, Usage,
 };
 
 use crate::napm::*;
+use crate::pkg::PkgInfo;
 
 impl Napm {
     pub fn h(&self) -> &Alpm {
@@ -27,8 +28,17 @@ impl Napm {
             .collect()
     }
 
+    /// Sync databases whose `Usage` in pacman.conf allows `usage`
+    pub fn syncdbs_with_usage(&self, usage: Usage) -> Vec<&Db> {
+        self.h()
+            .syncdbs()
+            .iter()
+            .filter(|db| db.usage().is_ok_and(|u| u.contains(usage)))
+            .collect()
+    }
+
     pub fn pkg(&self, name: &str) -> Result<Pkg> {
-        for db in self.h().syncdbs() {
+        for db in self.syncdbs_with_usage(Usage::INSTALL) {
             match db.pkg(name) {
                 Ok(pkg) => {
                     return Ok(Pkg::from(pkg));
@@ -40,6 +50,29 @@ impl Napm {
         Err(Error::PackageNotFound(name.to_string()))
     }
 
+    /// Sync (highest priority searchable repo, from the cache) and local metadata of a package
+    pub fn pkg_info(&self, name: &str) -> Result<(Option<PkgInfo>, Option<PkgInfo>)> {
+        let handle = self.h();
+
+        let sync = match self.cached_pkg_info(name) {
+            Ok(info) => Some(info),
+            Err(Error::PackageNotFound(_)) => None,
+            Err(err) => return Err(err),
+        };
+
+        let local = handle
+            .localdb()
+            .pkg(name)
+            .ok()
+            .map(|pkg| PkgInfo::new(pkg, handle));
+
+        if sync.is_none() && local.is_none() {
+            return Err(Error::PackageNotFound(name.to_string()));
+        }
+
+        Ok((sync, local))
+    }
+
     pub fn pkgs(&self, names: &[&str]) -> Vec<Result<Pkg>> {
         names
             .iter()
@@ -47,6 +80,29 @@ impl Napm {
             .collect()
     }
 
+    pub fn parse_usage(values: &[String]) -> Result<Usage> {
+        if values.is_empty() {
+            return Ok(Usage::ALL);
+        }
+
+        let mut usage = Usage::NONE;
+
+        for v in values {
+            match v.as_str() {
+                "Sync" => usage |= Usage::SYNC,
+                "Search" => usage |= Usage::SEARCH,
+                "Install" => usage |= Usage::INSTALL,
+                "Upgrade" => usage |= Usage::UPGRADE,
+                "All" => usage |= Usage::ALL,
+                other => {
+                    return Err(Error::UsageParse(other.to_string()));
+                }
+            }
+        }
+
+        Ok(usage)
+    }
+
     pub fn parse_siglevel(values: &[String]) -> Result<SigLevel> {
         let mut level = SigLevel::empty();
 
@@ -0,0 +1,50 @@
+use serde::Serialize;
+
+use crate::error::Result;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable, coloured output
+    #[default]
+    Text,
+    /// A single JSON document on stdout, see README.md for the schema
+    Json,
+    /// One record per line, tab separated fields, no header
+    Tsv,
+}
+
+impl OutputFormat {
+    pub fn is_text(&self) -> bool {
+        *self == OutputFormat::Text
+    }
+}
+
+pub fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<()> {
+    let json = serde_json::to_string_pretty(value).map_err(std::io::Error::other)?;
+    println!("{json}");
+
+    Ok(())
+}
+
+fn tsv_field(field: &str) -> String {
+    field.replace(['\t', '\n', '\r'], " ")
+}
+
+pub fn print_tsv<I, R, F>(rows: I) -> Result<()>
+where
+    I: IntoIterator<Item = R>,
+    R: IntoIterator<Item = F>,
+    F: AsRef<str>,
+{
+    for row in rows {
+        println!(
+            "{}",
+            row.into_iter()
+                .map(|f| tsv_field(f.as_ref()))
+                .collect::<Vec<_>>()
+                .join("\t")
+        );
+    }
+
+    Ok(())
+}
@@ -1,9 +1,10 @@
-use alpm::{Alpm, Package};
+use alpm::{Alpm, Package, PackageReason, PackageValidation};
+use serde::Serialize;
 
 use crate::error::{Error, Result};
 use crate::ansi::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Pkg {
     pub name: String,
     pub version: String,
@@ -65,4 +66,139 @@ impl From<&Package> for Pkg {
             desc: package.desc().unwrap_or("").to_string(),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Package archive given to `install` by path or URL instead of by name
+#[derive(Debug, Clone)]
+pub struct PkgFile {
+    pub path: String,
+    /// Downloaded from a URL, so verified with `RemoteFileSigLevel` instead of `LocalFileSigLevel`
+    pub remote: bool,
+}
+
+impl PkgFile {
+    /// Like `pacman -U`, URLs, paths and existing files are package files rather than package names
+    pub fn is_target(target: &str) -> bool {
+        // URLs contain a '/' too
+        target.contains('/')
+            || target.contains(".pkg.tar")
+            || std::path::Path::new(target).is_file()
+    }
+}
+
+/// Dependency that nothing installed requires anymore
+#[derive(Debug, Clone, Serialize)]
+pub struct Orphan {
+    #[serde(flatten)]
+    pub package: Pkg,
+    /// Installed packages that only optionally depend on it, empty for true orphans
+    pub optional_for: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OptDep {
+    pub dep: String,
+    pub desc: Option<String>,
+    pub installed: bool,
+}
+
+/// Full package metadata, as shown by `napm info`
+#[derive(Debug, Clone, Serialize)]
+pub struct PkgInfo {
+    pub name: String,
+    pub version: String,
+    pub repo: String,
+    pub desc: String,
+    pub arch: Option<String>,
+    pub url: Option<String>,
+    pub licenses: Vec<String>,
+    pub groups: Vec<String>,
+    pub provides: Vec<String>,
+    pub depends: Vec<String>,
+    pub optdepends: Vec<OptDep>,
+    pub required_by: Vec<String>,
+    pub optional_for: Vec<String>,
+    pub conflicts: Vec<String>,
+    pub replaces: Vec<String>,
+    /// Only known for sync packages
+    pub download_size: Option<i64>,
+    pub installed_size: i64,
+    pub packager: Option<String>,
+    pub build_date: i64,
+    /// Only known for installed packages
+    pub install_date: Option<i64>,
+    /// `explicit` or `dependency`, only known for installed packages
+    pub install_reason: Option<String>,
+    /// Only known for installed packages
+    pub validation: Vec<String>,
+}
+
+impl PkgInfo {
+    pub fn new(package: &Package, handle: &Alpm) -> Self {
+        let is_local = package.db().is_none_or(|db| db.name() == "local");
+
+        let deps = |list: alpm::AlpmList<&alpm::Dep>| list.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+
+        let installed = handle.localdb().pkgs();
+
+        let optdepends = package
+            .optdepends()
+            .iter()
+            .map(|d| {
+                let dep = match d.desc() {
+                    Some(desc) => d.to_string().trim_end_matches(desc).trim_end_matches(": ").to_string(),
+                    None => d.to_string(),
+                };
+
+                OptDep {
+                    installed: installed.find_satisfier(dep.as_str()).is_some(),
+                    desc: d.desc().map(str::to_string),
+                    dep,
+                }
+            })
+            .collect();
+
+        Self {
+            name: package.name().to_string(),
+            version: package.version().to_string(),
+            repo: package.db().map(|db| db.name()).unwrap_or("local").to_string(),
+            desc: package.desc().unwrap_or("").to_string(),
+            arch: package.arch().map(str::to_string),
+            url: package.url().map(str::to_string),
+            licenses: package.licenses().iter().map(str::to_string).collect(),
+            groups: package.groups().iter().map(str::to_string).collect(),
+            provides: deps(package.provides()),
+            depends: deps(package.depends()),
+            optdepends,
+            required_by: package.required_by().iter().map(|s| s.to_string()).collect(),
+            optional_for: package.optional_for().iter().map(|s| s.to_string()).collect(),
+            conflicts: deps(package.conflicts()),
+            replaces: deps(package.replaces()),
+            download_size: (!is_local).then(|| package.download_size()),
+            installed_size: package.isize(),
+            packager: package.packager().map(str::to_string),
+            build_date: package.build_date(),
+            install_date: package.install_date(),
+            install_reason: is_local.then(|| match package.reason() {
+                PackageReason::Explicit => "explicit",
+                PackageReason::Depend => "dependency",
+            }.to_string()),
+            validation: if is_local {
+                let v = package.validation();
+
+                [
+                    (PackageValidation::NONE, "none"),
+                    (PackageValidation::MD5SUM, "md5sum"),
+                    (PackageValidation::SHA256SUM, "sha256sum"),
+                    (PackageValidation::SIGNATURE, "signature"),
+                ]
+                .into_iter()
+                .filter(|(flag, _)| v.contains(*flag) && !flag.is_empty())
+                .map(|(_, name)| name.to_string())
+                .collect()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
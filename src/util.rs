@@ -3,15 +3,42 @@ use std::path::Path;
 use std::process::Command;
 use std::os::unix::process::CommandExt;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::ansi::*;
 use crate::error::{Error, Result};
 use crate::{format_action_required, log_info, log_warn, log_error};
-use crate::napm::cache::NAPM_CACHE_FILE;
+
+static NO_CONFIRM: AtomicBool = AtomicBool::new(false);
+
+/// Makes `confirm` and `choose` take their defaults instead of reading stdin
+pub fn set_no_confirm(no_confirm: bool) {
+    NO_CONFIRM.store(no_confirm, Ordering::Relaxed);
+}
+
+/// Whether a prompt can be shown, `Ok(false)` means the default should be used
+fn can_prompt(prompt: &str) -> Result<bool> {
+    use std::io::{self, IsTerminal};
+
+    if NO_CONFIRM.load(Ordering::Relaxed) {
+        return Ok(false);
+    }
+
+    if !io::stdin().is_terminal() {
+        return Err(Error::NotInteractive(prompt.to_string()));
+    }
+
+    Ok(true)
+}
 
 pub fn confirm(prompt: &str, default_yes: bool) -> Result<bool> {
     use std::io::{self, Write};
 
+    if !can_prompt(prompt)? {
+        log_info!("{} [{}, by default]", prompt, if default_yes { "yes" } else { "no" });
+        return Ok(default_yes);
+    }
+
     loop {
         eprint!("{}", format_action_required!("{} [{}]: ", prompt, if default_yes { "Y/n" } else { "y/N" }));
         io::stderr().flush()?;
@@ -38,6 +65,11 @@ pub fn confirm(prompt: &str, default_yes: bool) -> Result<bool> {
 pub fn choose(prompt: &str, options: &[String], default: i32) -> Result<i32> {
     use std::io::{self, Write};
 
+    if !can_prompt(prompt)? {
+        log_info!("{} [{}, by default]", prompt, options.get(default as usize).map(String::as_str).unwrap_or("?"));
+        return Ok(default);
+    }
+
     loop {
         log_warn!("{}", prompt);
 
@@ -68,6 +100,83 @@ pub fn choose(prompt: &str, options: &[String], default: i32) -> Result<i32> {
     }
 }
 
+/// Shell-style pattern matching (`*` and `?`), as used by pacman.conf lists like HoldPkg
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp + 1;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Human readable byte size, keeps the sign for net size changes
+pub fn format_size(bytes: i64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes.unsigned_abs() as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    let sign = if bytes < 0 { "-" } else { "" };
+
+    if unit == 0 {
+        format!("{sign}{size} {}", UNITS[unit])
+    } else {
+        format!("{sign}{size:.2} {}", UNITS[unit])
+    }
+}
+
+/// Formats a unix timestamp as `YYYY-MM-DD HH:MM:SS UTC`
+pub fn format_timestamp(ts: i64) -> String {
+    let days = ts.div_euclid(86400);
+    let secs = ts.rem_euclid(86400);
+
+    // days since 1970-01-01 to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
 pub const PE_TOOLS: &[&str] = &["sudo", "doas", "pkexec"];
 
 fn detect_pe_program() -> Result<String> {
@@ -242,14 +351,12 @@ pub fn require_root() -> Result<()> {
     Err(cmd.exec().into())
 }
 
-pub fn require_cache() -> Result<()> {
-    let cache_path = Path::new(NAPM_CACHE_FILE);
-    
-    if cache_path.exists() {
+pub fn require_cache(cache_path: &Path, outdated: bool, update_args: Vec<String>) -> Result<()> {
+    if cache_path.exists() && !outdated {
         return Ok(());
     }
 
-    let (mut cmd, cmd_display) = napm_as_root_cmd(vec!["update".to_string()])?;
+    let (mut cmd, cmd_display) = napm_as_root_cmd(update_args)?;
 
     if is_root() {
         log_info!("# {}", cmd_display);